@@ -0,0 +1,212 @@
+use hyper::{Method, StatusCode};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+
+use crate::log_sink::LogSink;
+use crate::rotation::{RotatingFile, Rotation};
+
+pub enum AccessLogOutput {
+    Stdout(tokio::io::Stdout),
+    File(Arc<std::sync::Mutex<RotatingFile>>),
+    Sink(Arc<LogSink>),
+}
+
+impl AccessLogOutput {
+    /// Open the log file at `path`, or stdout for "-"
+    pub fn open(path: &Path, rotation: Rotation) -> std::io::Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::Stdout(tokio::io::stdout()));
+        }
+
+        let file = RotatingFile::open(path, rotation)?;
+        Ok(Self::File(Arc::new(std::sync::Mutex::new(file))))
+    }
+}
+
+/// Which requests are logged, by URL path prefix.
+///
+/// The longest matching prefix decides: its requests are logged 1 in N, and never when N is
+/// 0. Paths without a matching rule are always logged.
+pub struct AccessLogFilter {
+    rules: Vec<(String, u64, AtomicU64)>,
+}
+
+impl AccessLogFilter {
+    pub fn new(excluded: &[String], sampled: &[(String, u64)]) -> Self {
+        let mut rules: Vec<(String, u64, AtomicU64)> = excluded
+            .iter()
+            .map(|prefix| (prefix.to_owned(), 0))
+            .chain(sampled.iter().cloned())
+            .map(|(prefix, every)| (prefix, every, AtomicU64::new(0)))
+            .collect();
+        // the longest prefix wins
+        rules.sort_by_key(|(prefix, _, _)| std::cmp::Reverse(prefix.len()));
+
+        Self { rules }
+    }
+
+    pub fn allows(&self, path: &str) -> bool {
+        let rule = self.rules.iter().find(|(prefix, _, _)| {
+            path.strip_prefix(prefix.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+
+        match rule {
+            None => true,
+            Some((_, 0, _)) => false,
+            Some((_, every, seen)) => seen.fetch_add(1, Ordering::Relaxed) % every == 0,
+        }
+    }
+}
+
+/// Parse a `PREFIX=N` sampling rule
+pub fn parse_sample_rule(rule: &str) -> Result<(String, u64), String> {
+    let Some((prefix, every)) = rule.split_once('=') else {
+        return Err(format!("sampling rule must be PREFIX=N: {rule}"));
+    };
+
+    if !prefix.starts_with('/') {
+        return Err(format!("route prefix must start with '/': {prefix}"));
+    }
+
+    let every = every
+        .parse::<u64>()
+        .map_err(|error| format!("invalid sampling rate {every}: {error}"))?;
+
+    Ok((prefix.to_owned(), every))
+}
+
+/// One line of the access log
+pub struct AccessLogEntry {
+    pub time: SystemTime,
+    pub client_addr: SocketAddr,
+    pub method: Method,
+    pub uri: String,
+    pub upstream: Option<String>,
+    pub status: StatusCode,
+    pub bytes: Option<u64>,
+    pub duration: Duration,
+}
+
+impl AccessLogEntry {
+    fn format(&self) -> String {
+        format!(
+            "{} - - [{}] \"{} {}\" {} {} \"{}\" {:.3}ms\n",
+            self.client_addr.ip(),
+            httpdate::fmt_http_date(self.time),
+            self.method,
+            self.uri,
+            self.status.as_u16(),
+            self.bytes.map_or("-".to_owned(), |bytes| bytes.to_string()),
+            self.upstream.as_deref().unwrap_or("-"),
+            self.duration.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Access log written by a dedicated task, so request handling never waits on log I/O.
+///
+/// Lines are queued in a bounded buffer and written in batches. When the writer falls behind
+/// and the buffer is full, the oldest lines are dropped.
+pub struct AccessLog {
+    queue: std::sync::Mutex<VecDeque<String>>,
+    capacity: usize,
+    dropped: AtomicUsize,
+    notify: Notify,
+    output: tokio::sync::Mutex<AccessLogOutput>,
+}
+
+impl AccessLog {
+    /// Start the writer task for the output
+    pub fn start(output: AccessLogOutput, capacity: usize) -> Arc<Self> {
+        let access_log = Arc::new(Self {
+            queue: std::sync::Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            dropped: AtomicUsize::new(0),
+            notify: Notify::new(),
+            output: tokio::sync::Mutex::new(output),
+        });
+
+        let writer = access_log.clone();
+        tokio::spawn(async move {
+            loop {
+                writer.notify.notified().await;
+                writer.flush().await;
+            }
+        });
+
+        access_log
+    }
+
+    pub fn log(&self, entry: AccessLogEntry) {
+        let line = entry.format();
+
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= self.capacity {
+                queue.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back(line);
+        }
+
+        self.notify.notify_one();
+    }
+
+    /// Write out everything queued so far, as a single batch
+    pub async fn flush(&self) {
+        let batch: String = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.drain(..).collect()
+        };
+
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("Access log fell behind, dropped {dropped} entries");
+        }
+
+        if batch.is_empty() {
+            return;
+        }
+
+        let mut output = self.output.lock().await;
+        let result = match &mut *output {
+            AccessLogOutput::Stdout(stdout) => match stdout.write_all(batch.as_bytes()).await {
+                Ok(()) => stdout.flush().await,
+                Err(error) => Err(error),
+            },
+            // rotation renames files, so the file is written from a blocking thread
+            AccessLogOutput::File(file) => {
+                let file = file.clone();
+                tokio::task::spawn_blocking(move || {
+                    let mut file = file.lock().unwrap();
+                    file.write_all(batch.as_bytes())?;
+                    file.flush()
+                })
+                .await
+                .unwrap_or_else(|error| Err(std::io::Error::other(error)))
+            }
+            AccessLogOutput::Sink(sink) => {
+                let sink = sink.clone();
+                tokio::task::spawn_blocking(move || {
+                    batch
+                        .lines()
+                        .try_for_each(|line| sink.send(log::Level::Info, "access", line))
+                })
+                .await
+                .unwrap_or_else(|error| Err(std::io::Error::other(error)))
+            }
+        };
+
+        if let Err(error) = result {
+            log::error!("Failed to write access log: {error}");
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::body::ProxyBody;
+use crate::error_page::error_response;
+use crate::secret::{self, Secret};
+
+/// Header carrying API keys when the route does not name one
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+
+/// How a route expects its credential
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <token>`
+    Bearer,
+    /// The key as the whole value of this header, lowercase
+    ApiKey(String),
+}
+
+/// Credentials accepted under a path prefix
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuthRoute {
+    prefix: String,
+    scheme: AuthScheme,
+    tokens: Vec<Secret>,
+}
+
+/// Simulated authentication in front of mocks, static files and utility endpoints.
+///
+/// The route with the longest matching prefix decides. A missing or unknown credential is
+/// answered with 401, an expired token with 401 saying so, and a credential accepted by
+/// another route with 403, so clients' auth failure handling can be tested without
+/// writing a mock for every case.
+pub struct AuthSimulation {
+    routes: Vec<AuthRoute>,
+    expired: Vec<Secret>,
+}
+
+impl AuthSimulation {
+    pub fn new(routes: Vec<AuthRoute>, expired: Vec<Secret>) -> Self {
+        Self { routes, expired }
+    }
+
+    /// The rejection for a request to the path, or `None` when it is allowed
+    pub fn check(&self, path: &str, headers: &HeaderMap) -> Option<Response<ProxyBody>> {
+        let route = self
+            .routes
+            .iter()
+            .filter(|route| path.starts_with(&route.prefix))
+            .max_by_key(|route| route.prefix.len())?;
+
+        let Some(credential) = route.scheme.credential(headers) else {
+            return Some(route.reject(401, "Unauthorized: credential is missing", None));
+        };
+
+        if route.accepts(credential) {
+            return None;
+        }
+
+        if self
+            .expired
+            .iter()
+            .any(|token| token.expose() == credential)
+        {
+            return Some(route.reject(
+                401,
+                "Unauthorized: token has expired",
+                Some(("invalid_token", "The access token expired")),
+            ));
+        }
+
+        let known_elsewhere = self
+            .routes
+            .iter()
+            .any(|other| other.scheme == route.scheme && other.accepts(credential));
+        if known_elsewhere {
+            return Some(route.reject(
+                403,
+                format!("Forbidden: credential is not accepted for {}", route.prefix),
+                Some((
+                    "insufficient_scope",
+                    "The access token does not grant this resource",
+                )),
+            ));
+        }
+
+        Some(route.reject(
+            401,
+            "Unauthorized: credential is not valid",
+            Some(("invalid_token", "The access token is not valid")),
+        ))
+    }
+}
+
+impl AuthScheme {
+    fn credential<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let value = match self {
+            AuthScheme::Bearer => headers
+                .get(hyper::header::AUTHORIZATION)?
+                .to_str()
+                .ok()?
+                .strip_prefix("Bearer ")?,
+            AuthScheme::ApiKey(header) => headers.get(header.as_str())?.to_str().ok()?,
+        };
+        Some(value.trim())
+    }
+}
+
+impl AuthRoute {
+    fn accepts(&self, credential: &str) -> bool {
+        self.tokens.iter().any(|token| token.expose() == credential)
+    }
+
+    fn reject(
+        &self,
+        status: u16,
+        message: impl Into<String>,
+        error: Option<(&str, &str)>,
+    ) -> Response<ProxyBody> {
+        let mut response = error_response(status, message);
+
+        // API keys have no standard challenge, so name the header they go in
+        let challenge = match (&self.scheme, error) {
+            (AuthScheme::Bearer, None) => "Bearer realm=\"affogato\"".to_owned(),
+            (AuthScheme::Bearer, Some((error, description))) => format!(
+                "Bearer realm=\"affogato\", error=\"{error}\", error_description=\"{description}\""
+            ),
+            (AuthScheme::ApiKey(header), _) => {
+                format!("ApiKey realm=\"affogato\", header=\"{header}\"")
+            }
+        };
+        if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+            response
+                .headers_mut()
+                .insert(hyper::header::WWW_AUTHENTICATE, challenge);
+        }
+        response
+    }
+}
+
+/// Parse `PREFIX=bearer:TOKEN[,TOKEN...]` or `PREFIX=api-key[:HEADER]:KEY[,KEY...]`,
+/// resolving `${env:NAME}` and `${file:PATH}` references in the tokens
+pub fn parse_auth_route(value: &str) -> Result<AuthRoute, String> {
+    let usage = || {
+        format!(
+            "auth route must be PREFIX=bearer:TOKEN[,TOKEN...] or PREFIX=api-key[:HEADER]:KEY[,KEY...]: {value}"
+        )
+    };
+
+    let (prefix, rule) = value.split_once('=').ok_or_else(usage)?;
+    let (scheme, tokens) = if let Some(tokens) = rule.strip_prefix("bearer:") {
+        (AuthScheme::Bearer, tokens)
+    } else if let Some(rest) = rule.strip_prefix("api-key:") {
+        // references hold a colon too, but `{` is never part of a header name
+        match rest
+            .split_once(':')
+            .and_then(|(header, tokens)| Some((HeaderName::try_from(header).ok()?, tokens)))
+        {
+            Some((header, tokens)) => (AuthScheme::ApiKey(header.as_str().to_owned()), tokens),
+            None => (AuthScheme::ApiKey(DEFAULT_API_KEY_HEADER.to_owned()), rest),
+        }
+    } else {
+        return Err(usage());
+    };
+
+    let tokens = tokens
+        .split(',')
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(secret::parse_secret)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !prefix.starts_with('/') || tokens.is_empty() {
+        return Err(usage());
+    }
+
+    Ok(AuthRoute {
+        prefix: prefix.to_owned(),
+        scheme,
+        tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulation() -> AuthSimulation {
+        AuthSimulation::new(
+            vec![
+                parse_auth_route("/api=bearer:user-token,other-token").unwrap(),
+                parse_auth_route("/api/admin=bearer:admin-token").unwrap(),
+                parse_auth_route("/partner=api-key:X-Partner-Key:partner-key").unwrap(),
+            ],
+            vec![secret::parse_secret("old-token").unwrap()],
+        )
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn status(response: Option<Response<ProxyBody>>) -> Option<u16> {
+        response.map(|response| response.status().as_u16())
+    }
+
+    #[test]
+    fn parses_schemes_and_headers() {
+        let route = parse_auth_route("/v1=api-key:key1, key2").unwrap();
+        assert_eq!(
+            route.scheme,
+            AuthScheme::ApiKey(DEFAULT_API_KEY_HEADER.to_owned())
+        );
+        assert_eq!(route.tokens.len(), 2);
+
+        let route = parse_auth_route("/v1=api-key:X-Key:key1").unwrap();
+        assert_eq!(route.scheme, AuthScheme::ApiKey("x-key".to_owned()));
+
+        assert!(parse_auth_route("/v1=basic:user").is_err());
+        assert!(parse_auth_route("v1=bearer:token").is_err());
+        assert!(parse_auth_route("/v1=bearer:").is_err());
+    }
+
+    #[test]
+    fn accepts_the_route_credential() {
+        let auth = simulation();
+        let bearer = headers("authorization", "Bearer other-token");
+        assert_eq!(status(auth.check("/api/users", &bearer)), None);
+
+        let key = headers("x-partner-key", "partner-key");
+        assert_eq!(status(auth.check("/partner/orders", &key)), None);
+
+        assert_eq!(status(auth.check("/public", &HeaderMap::new())), None);
+    }
+
+    #[test]
+    fn rejects_missing_unknown_and_expired_credentials() {
+        let auth = simulation();
+        assert_eq!(
+            status(auth.check("/api/users", &HeaderMap::new())),
+            Some(401)
+        );
+
+        let unknown = headers("authorization", "Bearer made-up");
+        assert_eq!(status(auth.check("/api/users", &unknown)), Some(401));
+
+        let expired = auth
+            .check("/api/users", &headers("authorization", "Bearer old-token"))
+            .unwrap();
+        assert_eq!(expired.status(), 401);
+        let challenge = expired.headers()[hyper::header::WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap();
+        assert!(challenge.contains("The access token expired"));
+    }
+
+    #[test]
+    fn forbids_credentials_of_other_routes() {
+        let auth = simulation();
+        let user = headers("authorization", "Bearer user-token");
+        assert_eq!(status(auth.check("/api/admin/users", &user)), Some(403));
+
+        // routes do not inherit tokens from each other in either direction
+        let admin = headers("authorization", "Bearer admin-token");
+        assert_eq!(status(auth.check("/api/users", &admin)), Some(403));
+    }
+}
@@ -0,0 +1,137 @@
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cli::BenchFlags;
+use crate::client;
+use crate::constants::PROXY_HOST_HEADER;
+
+#[derive(Default)]
+struct WorkerReport {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    errors: usize,
+}
+
+/// Send `flags.requests` requests to the target with `flags.concurrency` workers,
+/// and print throughput and latency percentiles.
+pub async fn run(flags: BenchFlags) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = client::build_client()?;
+    let method = Method::from_str(&flags.method)?;
+
+    let mut headers = HeaderMap::new();
+    for header in &flags.header {
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(format!("header must be NAME:VALUE: {header}").into());
+        };
+        headers.append(
+            HeaderName::from_str(name.trim())?,
+            HeaderValue::from_str(value.trim())?,
+        );
+    }
+    if let Some(proxy_host) = &flags.proxy_host {
+        headers.insert(PROXY_HOST_HEADER, HeaderValue::from_str(proxy_host)?);
+    }
+
+    let body = flags.data.clone().unwrap_or_default();
+    let remaining = Arc::new(AtomicUsize::new(flags.requests));
+
+    println!(
+        "Running {} requests with {} workers: {method} {}",
+        flags.requests, flags.concurrency, flags.url
+    );
+
+    let started_at = Instant::now();
+
+    let workers: Vec<_> = (0..flags.concurrency.max(1))
+        .map(|_| {
+            let client = client.clone();
+            let method = method.clone();
+            let url = flags.url.clone();
+            let headers = headers.clone();
+            let body = body.clone();
+            let remaining = remaining.clone();
+
+            tokio::spawn(async move {
+                let mut report = WorkerReport::default();
+
+                while remaining
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok()
+                {
+                    let sent_at = Instant::now();
+                    let result = client
+                        .request(method.clone(), &url)
+                        .headers(headers.clone())
+                        .body(body.clone())
+                        .send()
+                        .await;
+
+                    match result {
+                        Ok(response) => {
+                            let status = response.status().as_u16();
+                            if response.bytes().await.is_err() {
+                                report.errors += 1;
+                                continue;
+                            }
+                            report.latencies.push(sent_at.elapsed());
+                            *report.statuses.entry(status).or_default() += 1;
+                        }
+                        Err(_) => report.errors += 1,
+                    }
+                }
+
+                report
+            })
+        })
+        .collect();
+
+    let mut total = WorkerReport::default();
+    for worker in workers {
+        let report = worker.await?;
+        total.latencies.extend(report.latencies);
+        total.errors += report.errors;
+        for (status, count) in report.statuses {
+            *total.statuses.entry(status).or_default() += count;
+        }
+    }
+
+    let elapsed = started_at.elapsed();
+    total.latencies.sort();
+
+    let completed = total.latencies.len();
+    println!();
+    println!("Completed:  {completed} in {elapsed:.2?}");
+    println!("Errors:     {}", total.errors);
+    println!(
+        "Throughput: {:.1} req/s",
+        completed as f64 / elapsed.as_secs_f64()
+    );
+
+    if completed > 0 {
+        println!();
+        println!("Latency");
+        for (label, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999)] {
+            println!("  {label:<6}{:.2?}", percentile(&total.latencies, quantile));
+        }
+        println!("  {:<6}{:.2?}", "max", total.latencies[completed - 1]);
+    }
+
+    println!();
+    println!("Status codes");
+    for (status, count) in &total.statuses {
+        println!("  {status}: {count}");
+    }
+
+    Ok(())
+}
+
+/// Nearest-rank percentile of sorted latencies
+pub fn percentile(sorted: &[Duration], quantile: f64) -> Duration {
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
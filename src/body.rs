@@ -0,0 +1,24 @@
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::HeaderMap;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Response body type returned to clients
+pub type ProxyBody = BoxBody<Bytes, BoxError>;
+
+/// A body holding `data` in memory
+pub fn full(data: impl Into<Bytes>) -> ProxyBody {
+    Full::new(data.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// A body holding `data` in memory, followed by `trailers`
+pub fn full_with_trailers(data: impl Into<Bytes>, trailers: HeaderMap) -> ProxyBody {
+    Full::new(data.into())
+        .with_trailers(std::future::ready(Some(Ok(trailers))))
+        .map_err(|never| match never {})
+        .boxed()
+}
@@ -0,0 +1,346 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::{HeaderMap, Method, StatusCode};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+
+use crate::clock;
+use crate::redact::Redactor;
+use crate::timing::{self, UpstreamTiming};
+
+/// Start of the comment on bodies cut to the size limit
+pub const TRUNCATED_MARKER: &str = "truncated";
+
+/// One proxied request and its upstream response
+pub struct Exchange<'a> {
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub timing: UpstreamTiming,
+    pub method: &'a Method,
+    pub url: &'a str,
+    pub request_headers: &'a HeaderMap,
+    pub request_body: &'a [u8],
+    pub status: StatusCode,
+    pub response_headers: &'a HeaderMap,
+    pub response_body: &'a [u8],
+}
+
+/// Writes proxied exchanges to a HAR (HTTP Archive 1.2) file, which browsers' developer
+/// tools and most HTTP debugging tools can open.
+///
+/// Entries are appended by a writer task as they come in, and the document is closed by
+/// `finish` at shutdown. Headers, URLs and bodies pass through the redaction rules first.
+/// Bodies beyond `max_body_size` are cut, with a comment giving their original size.
+/// Bodies still larger than the spill size are written to their own files in a directory
+/// next to the HAR file, and referenced by a `_file` field, so they are not held in memory
+/// until the writer catches up.
+pub struct TrafficCapture {
+    max_body_size: Option<usize>,
+    spill: Option<Spill>,
+    queue: std::sync::Mutex<Vec<String>>,
+    notify: Notify,
+    output: tokio::sync::Mutex<CaptureFile>,
+}
+
+/// Directory for bodies above `threshold` bytes
+struct Spill {
+    threshold: usize,
+    dir: PathBuf,
+    /// The directory as referenced from entries, relative to the HAR file
+    reference: String,
+    next_id: AtomicU64,
+}
+
+/// Where one body goes if it is above the threshold
+struct SpillTarget {
+    threshold: usize,
+    path: PathBuf,
+    reference: String,
+}
+
+impl Spill {
+    fn target(&self, id: u64, part: &str) -> SpillTarget {
+        let name = format!("{id:06}-{part}.bin");
+        SpillTarget {
+            threshold: self.threshold,
+            path: self.dir.join(&name),
+            reference: format!("{}/{name}", self.reference),
+        }
+    }
+}
+
+struct CaptureFile {
+    file: tokio::fs::File,
+    entries: usize,
+    finished: bool,
+}
+
+impl TrafficCapture {
+    pub fn start(
+        path: &Path,
+        max_body_size: Option<usize>,
+        spill_size: Option<usize>,
+    ) -> std::io::Result<Arc<Self>> {
+        let header = json!({
+            "version": "1.2",
+            "creator": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+        })
+        .to_string();
+        // the entries array is left open, and closed by finish()
+        let header = format!("{{\"log\":{},\"entries\":[\n", &header[..header.len() - 1]);
+        std::fs::write(path, header)?;
+
+        let file = std::fs::OpenOptions::new().append(true).open(path)?;
+
+        let spill = match spill_size {
+            Some(threshold) => {
+                let mut dir = path.as_os_str().to_owned();
+                dir.push(".bodies");
+                let dir = PathBuf::from(dir);
+                std::fs::create_dir_all(&dir)?;
+
+                Some(Spill {
+                    threshold,
+                    reference: dir
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    dir,
+                    next_id: AtomicU64::new(1),
+                })
+            }
+            None => None,
+        };
+
+        let capture = Arc::new(Self {
+            max_body_size,
+            spill,
+            queue: std::sync::Mutex::new(Vec::new()),
+            notify: Notify::new(),
+            output: tokio::sync::Mutex::new(CaptureFile {
+                file: tokio::fs::File::from_std(file),
+                entries: 0,
+                finished: false,
+            }),
+        });
+
+        let writer = capture.clone();
+        tokio::spawn(async move {
+            loop {
+                writer.notify.notified().await;
+                writer.flush().await;
+            }
+        });
+
+        Ok(capture)
+    }
+
+    pub async fn record(&self, exchange: &Exchange<'_>, redactor: &Redactor) {
+        let id = self
+            .spill
+            .as_ref()
+            .map(|spill| (spill, spill.next_id.fetch_add(1, Ordering::Relaxed)));
+        let mut spilled = Vec::new();
+        let entry = har_entry(exchange, redactor, self.max_body_size, id, &mut spilled);
+
+        for (path, body) in spilled {
+            if let Err(error) = tokio::fs::write(&path, body).await {
+                log::error!("Failed to write captured body {}: {error}", path.display());
+            }
+        }
+
+        self.queue.lock().unwrap().push(entry.to_string());
+        self.notify.notify_one();
+    }
+
+    async fn flush(&self) {
+        let entries: Vec<String> = std::mem::take(&mut *self.queue.lock().unwrap());
+        if entries.is_empty() {
+            return;
+        }
+
+        let mut output = self.output.lock().await;
+        if output.finished {
+            return;
+        }
+
+        let mut batch = String::new();
+        for entry in entries {
+            if output.entries > 0 {
+                batch.push_str(",\n");
+            }
+            batch.push_str(&entry);
+            output.entries += 1;
+        }
+
+        if let Err(error) = output.file.write_all(batch.as_bytes()).await {
+            log::error!("Failed to write traffic capture: {error}");
+        }
+    }
+
+    /// Write out pending entries and close the document
+    pub async fn finish(&self) {
+        self.flush().await;
+
+        let mut output = self.output.lock().await;
+        if output.finished {
+            return;
+        }
+        output.finished = true;
+
+        let result = match output.file.write_all(b"\n]}}\n").await {
+            Ok(()) => output.file.flush().await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            log::error!("Failed to finish traffic capture: {error}");
+        }
+    }
+}
+
+/// The HAR entry of an exchange, with the bodies inline
+pub fn har_record(exchange: &Exchange, redactor: &Redactor, max_body_size: Option<usize>) -> Value {
+    har_entry(exchange, redactor, max_body_size, None, &mut Vec::new())
+}
+
+/// The HAR entry of an exchange, with the bodies to write to their own files in `spilled`
+fn har_entry(
+    exchange: &Exchange,
+    redactor: &Redactor,
+    max_body_size: Option<usize>,
+    spill: Option<(&Spill, u64)>,
+    spilled: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Value {
+    let url = redactor.text(exchange.url);
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let query_string: Vec<Value> = form_urlencoded::parse(query.as_bytes())
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect();
+
+    let request_headers = redactor.headers(exchange.request_headers);
+    let response_headers = redactor.headers(exchange.response_headers);
+
+    let mut request = json!({
+        "method": exchange.method.as_str(),
+        "url": url,
+        "httpVersion": "HTTP/1.1",
+        "cookies": [],
+        "headers": har_headers(&request_headers),
+        "queryString": query_string,
+        "headersSize": -1,
+        "bodySize": exchange.request_body.len(),
+    });
+    if !exchange.request_body.is_empty() {
+        let mut post_data = har_content(
+            &request_headers,
+            &redactor.body(exchange.request_body),
+            max_body_size,
+            spill.map(|(spill, id)| spill.target(id, "request")),
+            spilled,
+        );
+        post_data["params"] = json!([]);
+        request["postData"] = post_data;
+    }
+
+    let mut content = har_content(
+        &response_headers,
+        &redactor.body(exchange.response_body),
+        max_body_size,
+        spill.map(|(spill, id)| spill.target(id, "response")),
+        spilled,
+    );
+    content["size"] = json!(exchange.response_body.len());
+
+    json!({
+        "startedDateTime": clock::rfc3339(exchange.started_at),
+        "time": timing::millis(exchange.duration),
+        "request": request,
+        "response": {
+            "status": exchange.status.as_u16(),
+            "statusText": exchange.status.canonical_reason().unwrap_or(""),
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": har_headers(&response_headers),
+            "content": content,
+            "redirectURL": response_headers
+                .get(hyper::header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or(""),
+            "headersSize": -1,
+            "bodySize": exchange.response_body.len(),
+        },
+        "cache": {},
+        "timings": {
+            "dns": timing::millis(exchange.timing.dns),
+            "connect": timing::millis(exchange.timing.connect),
+            "send": 0,
+            "wait": timing::millis(exchange.timing.queue + exchange.timing.ttfb),
+            "receive": timing::millis(exchange.timing.body),
+        },
+    })
+}
+
+fn har_headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({ "name": name.as_str(), "value": String::from_utf8_lossy(value.as_bytes()) })
+        })
+        .collect()
+}
+
+/// `mimeType` and `text` of a body, base64 encoded unless it is UTF-8, and cut to
+/// `max_body_size` bytes. A body above the spill threshold is added to `spilled` instead,
+/// and referenced by `_file`.
+fn har_content(
+    headers: &HeaderMap,
+    body: &[u8],
+    max_body_size: Option<usize>,
+    spill: Option<SpillTarget>,
+    spilled: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Value {
+    let mime_type = headers
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    let kept = match max_body_size {
+        Some(max_body_size) if body.len() > max_body_size => {
+            let kept = &body[..max_body_size];
+            // text cut in the middle of a character stays text
+            match std::str::from_utf8(kept) {
+                Err(error) if error.error_len().is_none() => &kept[..error.valid_up_to()],
+                _ => kept,
+            }
+        }
+        _ => body,
+    };
+
+    let mut content = match spill.filter(|spill| kept.len() > spill.threshold) {
+        Some(spill) => {
+            spilled.push((spill.path, kept.to_vec()));
+            json!({ "mimeType": mime_type, "text": "", "_file": spill.reference })
+        }
+        None => match std::str::from_utf8(kept) {
+            Ok(text) => json!({ "mimeType": mime_type, "text": text }),
+            Err(_) => {
+                json!({ "mimeType": mime_type, "text": STANDARD.encode(kept), "encoding": "base64" })
+            }
+        },
+    };
+
+    if kept.len() < body.len() {
+        content["comment"] = json!(format!(
+            "{TRUNCATED_MARKER} to {} of {} bytes",
+            kept.len(),
+            body.len()
+        ));
+    }
+
+    content
+}
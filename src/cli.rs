@@ -1,15 +1,108 @@
 use clap::Args;
 use clap::Parser;
-use serde::Deserialize;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use crate::client::{IpFamily, OutboundBind, PoolSettings};
+use crate::latency::LatencyProfile;
+use crate::log_sink::LogSinkKind;
+use crate::secret::Secret;
+use crate::signature::SignatureStyle;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Command {
+    #[clap(subcommand)]
+    pub action: Option<Action>,
+
     #[clap(flatten)]
     pub value: CommandFlags,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Args)]
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Drive concurrent load through the proxy (or directly at an upstream) and report latency percentiles
+    Bench(BenchFlags),
+    /// Send the requests of a HAR recording (e.g. from --capture-file) again, at recorded or scaled timing
+    Replay(ReplayFlags),
+    /// Inspect the proxy's configuration
+    Config {
+        #[clap(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the effective configuration, flags given before `config` merged with defaults, as JSON
+    Dump,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct BenchFlags {
+    #[clap(help = "URL to send requests to")]
+    pub url: String,
+
+    #[clap(
+        short,
+        long,
+        default_value = "10",
+        help = "number of concurrent workers"
+    )]
+    pub concurrency: usize,
+
+    #[clap(
+        short = 'n',
+        long,
+        default_value = "1000",
+        help = "total number of requests"
+    )]
+    pub requests: usize,
+
+    #[clap(short = 'X', long, default_value = "GET", help = "request method")]
+    pub method: String,
+
+    #[clap(short = 'H', long, help = "NAME:VALUE request header, can be repeated")]
+    pub header: Vec<String>,
+
+    #[clap(short, long, help = "request body")]
+    pub data: Option<String>,
+
+    #[clap(
+        long,
+        help = "upstream to send through the proxy as the Proxy-Host header"
+    )]
+    pub proxy_host: Option<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct ReplayFlags {
+    #[clap(help = "HAR file with the recorded requests")]
+    pub recording: std::path::PathBuf,
+
+    #[clap(
+        short,
+        long,
+        help = "base URL the recorded paths are sent to, e.g. http://localhost:8080"
+    )]
+    pub base_url: String,
+
+    #[clap(
+        short,
+        long,
+        default_value = "1",
+        help = "timing factor, 2 replays twice as fast, 0 sends every request without waiting"
+    )]
+    pub speed: f64,
+
+    #[clap(
+        long,
+        help = "upstream to send through the proxy as the Proxy-Host header"
+    )]
+    pub proxy_host: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Args)]
 pub struct CommandFlags {
     #[clap(
         short,
@@ -24,6 +117,626 @@ pub struct CommandFlags {
 
     #[clap(short, long, default_value = "0.0.0.0", help = "address to listen on")]
     pub address: String,
+
+    #[clap(
+        long,
+        help = "maximum number of in-flight requests per upstream, at least 1"
+    )]
+    pub upstream_max_in_flight: Option<std::num::NonZeroUsize>,
+
+    #[clap(
+        long,
+        default_value = "16",
+        help = "number of requests allowed to wait for a busy upstream before returning 503"
+    )]
+    pub upstream_queue_size: usize,
+
+    #[clap(
+        long,
+        help = "maximum number of idle upstream connections kept per host"
+    )]
+    pub upstream_pool_max_idle: Option<usize>,
+
+    #[clap(
+        long,
+        help = "close idle upstream connections after this many seconds (default 90)"
+    )]
+    pub upstream_pool_idle_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        value_parser = crate::client::parse_upstream_pool,
+        help = "connection pool settings for one upstream as UPSTREAM=MAX_IDLE[,IDLE_TIMEOUT], with UPSTREAM as given in Proxy-Host (e.g. http://api:8080=4,30); can be repeated"
+    )]
+    pub upstream_pool: Vec<(String, PoolSettings)>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "race",
+        help = "address family for upstream connections; the other family is raced after 300ms unless only one is allowed"
+    )]
+    pub ip_family: IpFamily,
+
+    #[clap(
+        long,
+        value_parser = crate::client::parse_upstream_ip_family,
+        help = "address family for one upstream as UPSTREAM=FAMILY, with UPSTREAM as given in Proxy-Host (e.g. http://api:8080=ipv4); can be repeated"
+    )]
+    pub upstream_ip_family: Vec<(String, IpFamily)>,
+
+    #[clap(
+        long,
+        value_parser = crate::client::parse_outbound_bind,
+        help = "local IP address or network interface for upstream connections"
+    )]
+    pub outbound_bind: Option<OutboundBind>,
+
+    #[clap(
+        long,
+        value_parser = crate::client::parse_upstream_outbound_bind,
+        help = "local IP address or network interface for one upstream as UPSTREAM=ADDRESS or UPSTREAM=INTERFACE, with UPSTREAM as given in Proxy-Host (e.g. http://api:8080=10.0.0.5); can be repeated"
+    )]
+    pub upstream_outbound_bind: Vec<(String, OutboundBind)>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "send upstream request header names in title case (Content-Type) instead of lowercase"
+    )]
+    pub title_case_headers: bool,
+
+    #[clap(
+        long,
+        help = "send request header names in title case to this upstream, as given in Proxy-Host; can be repeated"
+    )]
+    pub upstream_title_case_headers: Vec<String>,
+
+    #[clap(
+        long,
+        help = "send a duplicate upstream request for idempotent methods after this many milliseconds"
+    )]
+    pub hedge_delay: Option<u64>,
+
+    #[clap(
+        long,
+        help = "maximum size of upstream response bodies in bytes (502 beyond it)"
+    )]
+    pub max_response_body_size: Option<u64>,
+
+    #[clap(
+        long,
+        help = "maximum number of request headers; beyond it, clients get a 431 naming the limit"
+    )]
+    pub max_headers: Option<usize>,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(8192..),
+        help = "maximum size of the request head in bytes, at least 8192; beyond it, clients get a 431 naming the limit"
+    )]
+    pub max_header_size: Option<u64>,
+
+    #[clap(
+        long,
+        help = "serve a mock OpenID Connect issuer with this issuer URL (e.g. http://localhost:4443)"
+    )]
+    pub oidc_issuer: Option<String>,
+
+    #[clap(
+        long,
+        value_parser = crate::oidc::parse_claim,
+        help = "extra KEY=VALUE claim for issued tokens, can be repeated (VALUE may be JSON)"
+    )]
+    pub oidc_claim: Vec<(String, serde_json::Value)>,
+
+    #[clap(
+        long,
+        default_value = "3600",
+        help = "lifetime of issued tokens in seconds"
+    )]
+    pub oidc_token_ttl: u64,
+
+    #[clap(
+        long,
+        help = "require a valid bearer JWT on proxied requests, verified against this JWKS URL or file"
+    )]
+    pub jwt_jwks: Option<String>,
+
+    #[clap(long, help = "required issuer (iss) of bearer JWTs")]
+    pub jwt_issuer: Option<String>,
+
+    #[clap(long, help = "required audience (aud) of bearer JWTs")]
+    pub jwt_audience: Option<String>,
+
+    #[clap(
+        long,
+        help = "path prefix that requires a bearer JWT, can be repeated (default: all paths)"
+    )]
+    pub jwt_path: Vec<String>,
+
+    #[clap(
+        long,
+        value_parser = crate::auth::parse_auth_route,
+        help = "PREFIX=bearer:TOKEN[,TOKEN...] or PREFIX=api-key[:HEADER]:KEY[,KEY...] credentials required by requests without Proxy-Host under the prefix (401 when missing or unknown, 403 when accepted by another route), can be repeated"
+    )]
+    pub auth_route: Vec<crate::auth::AuthRoute>,
+
+    #[clap(
+        long,
+        value_parser = crate::secret::parse_secret,
+        help = "token answered as expired (401 invalid_token) on --auth-route prefixes, can be repeated"
+    )]
+    pub auth_expired_token: Vec<crate::secret::Secret>,
+
+    #[clap(
+        long,
+        help = "origin allowed to make state-changing requests without Proxy-Host (others get 403, checked against Origin or Referer), can be repeated"
+    )]
+    pub csrf_origin: Vec<String>,
+
+    #[clap(
+        long,
+        help = "require state-changing requests without Proxy-Host to repeat the value of this cookie in the CSRF header (403 otherwise)"
+    )]
+    pub csrf_cookie: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "X-CSRF-Token",
+        help = "header carrying the CSRF token checked against --csrf-cookie"
+    )]
+    pub csrf_header: String,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "answer CORS preflight requests without Proxy-Host for --csrf-origin origins (any origin without them), and add CORS headers to their responses"
+    )]
+    pub cors_preflight: bool,
+
+    #[clap(
+        long,
+        help = "path prefix the CSRF and preflight simulation applies to, can be repeated (default: all paths)"
+    )]
+    pub csrf_path: Vec<String>,
+
+    #[clap(
+        long,
+        value_parser = crate::secret::parse_secret,
+        help = "reject proxied requests without a valid HMAC webhook signature made with this secret (may reference ${env:NAME} or ${file:PATH})"
+    )]
+    pub verify_signature_secret: Option<Secret>,
+
+    #[clap(
+        long,
+        value_parser = crate::secret::parse_secret,
+        help = "sign proxied requests with an HMAC webhook signature made with this secret (may reference ${env:NAME} or ${file:PATH})"
+    )]
+    pub sign_secret: Option<Secret>,
+
+    #[clap(
+        long,
+        requires = "sigv4_secret_key",
+        value_parser = crate::secret::interpolate,
+        help = "sign proxied requests with AWS Signature Version 4 using this access key id (may reference ${env:NAME} or ${file:PATH})"
+    )]
+    pub sigv4_access_key: Option<String>,
+
+    #[clap(
+        long,
+        requires = "sigv4_access_key",
+        value_parser = crate::secret::parse_secret,
+        help = "AWS secret access key (may reference ${env:NAME} or ${file:PATH})"
+    )]
+    pub sigv4_secret_key: Option<Secret>,
+
+    #[clap(
+        long,
+        value_parser = crate::secret::parse_secret,
+        help = "AWS session token, for temporary credentials (may reference ${env:NAME} or ${file:PATH})"
+    )]
+    pub sigv4_session_token: Option<Secret>,
+
+    #[clap(
+        long,
+        help = "AWS region to sign for (default: from upstream hosts like sqs.us-east-1.amazonaws.com)"
+    )]
+    pub sigv4_region: Option<String>,
+
+    #[clap(
+        long,
+        help = "AWS service to sign for (default: from upstream hosts like sqs.us-east-1.amazonaws.com)"
+    )]
+    pub sigv4_service: Option<String>,
+
+    #[clap(
+        long,
+        help = "path prefix whose requests are signed with SigV4, can be repeated (default: all paths)"
+    )]
+    pub sigv4_path: Vec<String>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "github",
+        help = "webhook signature header format"
+    )]
+    pub signature_style: SignatureStyle,
+
+    #[clap(
+        long,
+        default_value = "0",
+        allow_negative_numbers = true,
+        value_parser = crate::clock::parse_offset,
+        help = "shift the proxy's clock by this many seconds (Date headers, issued and validated tokens, signatures)"
+    )]
+    pub clock_offset: i64,
+
+    #[clap(
+        long,
+        help = "read the clock offset on GET and set it on PUT (body: seconds) at this path (e.g. /_clock)"
+    )]
+    pub clock_path: Option<String>,
+
+    #[clap(
+        long,
+        help = "record proxied responses as snapshots in this directory, and report responses that differ from them"
+    )]
+    pub snapshot_dir: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "overwrite existing snapshots instead of comparing against them"
+    )]
+    pub snapshot_update: bool,
+
+    #[clap(long, help = "write an access log to this file (\"-\" for stdout)")]
+    pub access_log: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "8192",
+        help = "number of access log lines buffered before the oldest are dropped"
+    )]
+    pub access_log_buffer: usize,
+
+    #[clap(
+        long,
+        help = "path prefix left out of the access log, can be repeated (e.g. /health)"
+    )]
+    pub access_log_exclude: Vec<String>,
+
+    #[clap(
+        long,
+        value_parser = crate::access_log::parse_sample_rule,
+        help = "PREFIX=N to log 1 in N requests under PREFIX, can be repeated (the longest prefix wins, 0 logs none)"
+    )]
+    pub access_log_sample: Vec<(String, u64)>,
+
+    #[clap(
+        long,
+        help = "rotate the access log file once it would grow beyond this many bytes"
+    )]
+    pub access_log_max_size: Option<u64>,
+
+    #[clap(long, help = "rotate the access log file after this many seconds")]
+    pub access_log_rotate_interval: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "5",
+        help = "number of rotated access log files to keep"
+    )]
+    pub access_log_keep: usize,
+
+    #[clap(
+        long,
+        help = "record proxied exchanges to this HAR file, completed at shutdown"
+    )]
+    pub capture_file: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        help = "cut captured and mirrored request and response bodies to this many bytes, noting their original size"
+    )]
+    pub capture_max_body_size: Option<usize>,
+
+    #[clap(
+        long,
+        help = "write captured bodies larger than this many bytes to files in a .bodies directory next to the HAR file, instead of keeping them in memory"
+    )]
+    pub capture_spill_size: Option<usize>,
+
+    #[clap(
+        long,
+        help = "publish every proxied exchange as a JSON HAR entry to the NATS server at this host:port"
+    )]
+    pub mirror_nats: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "affogato.exchanges",
+        help = "NATS subject for --mirror-nats"
+    )]
+    pub mirror_nats_subject: String,
+
+    #[clap(long, help = "write application logs to this file instead of stderr")]
+    pub log_file: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        help = "rotate the log file once it would grow beyond this many bytes"
+    )]
+    pub log_max_size: Option<u64>,
+
+    #[clap(long, help = "rotate the log file after this many seconds")]
+    pub log_rotate_interval: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "5",
+        help = "number of rotated log files to keep"
+    )]
+    pub log_keep: usize,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "send application logs to syslog or journald instead of stderr"
+    )]
+    pub log_sink: Option<LogSinkKind>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "send the access log to syslog or journald instead of a file"
+    )]
+    pub access_log_sink: Option<LogSinkKind>,
+
+    #[clap(
+        long,
+        help = "syslog socket path or udp://host:port collector (default /dev/log)"
+    )]
+    pub syslog_address: Option<String>,
+
+    #[clap(long, help = "serve mock DNS over UDP and TCP on this port")]
+    pub dns_port: Option<u16>,
+
+    #[clap(
+        long,
+        value_parser = crate::dns::parse_record,
+        help = "NAME=ADDRESS record answered by the DNS server, can be repeated (*.NAME matches subdomains)"
+    )]
+    pub dns_record: Vec<(String, std::net::IpAddr)>,
+
+    #[clap(
+        long,
+        value_parser = crate::dns::parse_failure,
+        help = "NAME=MODE to make the DNS server fail for a name (nxdomain, servfail, refused, timeout), can be repeated"
+    )]
+    pub dns_failure: Vec<(String, crate::dns::DnsFailure)>,
+
+    #[clap(long, default_value = "60", help = "TTL of DNS answers in seconds")]
+    pub dns_ttl: u32,
+
+    #[clap(
+        long,
+        help = "accept email over SMTP on this port, and store each message as an .eml file"
+    )]
+    pub smtp_port: Option<u16>,
+
+    #[clap(
+        long,
+        default_value = "smtp",
+        help = "directory for emails captured over SMTP"
+    )]
+    pub smtp_dir: std::path::PathBuf,
+
+    #[clap(
+        long,
+        help = "serve httpbin-style endpoints (/echo, /status/{code}, /delay/{secs}, /headers, /ip, /bytes/{n}) under this prefix, e.g. / or /_util"
+    )]
+    pub utility_prefix: Option<String>,
+
+    #[clap(
+        long,
+        value_parser = crate::error_page::parse_error_page,
+        help = "STATUS=FILE template for errors generated by the proxy, can be repeated ({{status}}, {{reason}} and {{message}} are replaced)"
+    )]
+    pub error_page: Vec<(u16, std::path::PathBuf)>,
+
+    #[clap(
+        long,
+        value_parser = crate::header_profile::parse_header_profile,
+        help = "NAME=HEADER:VALUE header of a named response header profile, can be repeated (built in: security, cors, no-store, immutable)"
+    )]
+    pub header_profile: Vec<(String, String, String)>,
+
+    #[clap(
+        long,
+        value_parser = crate::header_profile::parse_header_route,
+        help = "PREFIX=PROFILE[,PROFILE...] header profiles added to mock and static file responses under the prefix, can be repeated"
+    )]
+    pub header_route: Vec<(String, Vec<String>)>,
+
+    #[clap(
+        long,
+        help = "answer requests without Proxy-Host from METHOD files in this directory (e.g. api/users/GET.json)"
+    )]
+    pub mock_dir: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        value_parser = crate::fs_mock::parse_expectation,
+        help = "FILE=N, FILE>=N or FILE<=N number of hits a mock file (relative to --mock-dir) should get, can be repeated; checked by --verify-path and on shutdown"
+    )]
+    pub mock_expect: Vec<crate::fs_mock::Expectation>,
+
+    #[clap(
+        long,
+        value_parser = crate::fs_mock::parse_limit,
+        help = "FILE=N to serve a mock file (relative to --mock-dir) only N times, after which the next file for the method answers, can be repeated"
+    )]
+    pub mock_limit: Vec<(std::path::PathBuf, u64)>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "replace ${env:NAME} in mock files with the environment variable (startup fails while one is unset)"
+    )]
+    pub mock_env: bool,
+
+    #[clap(
+        long,
+        requires = "mock_dir",
+        help = "clone this git repository into --mock-dir at startup, and keep it in sync (local changes are discarded)"
+    )]
+    pub mock_git_repo: Option<String>,
+
+    #[clap(
+        long,
+        help = "branch of --mock-git-repo (default: the remote's default branch)"
+    )]
+    pub mock_git_branch: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "60",
+        help = "seconds between syncs of --mock-git-repo, 0 to sync only at startup and on --mock-sync-path"
+    )]
+    pub mock_git_interval: u64,
+
+    #[clap(
+        long,
+        requires = "mock_git_repo",
+        help = "sync --mock-git-repo on POST to this path, answering with the checked out commit (e.g. /_sync)"
+    )]
+    pub mock_sync_path: Option<String>,
+
+    #[clap(
+        long,
+        help = "POST lifecycle events (started, upgrade-completed, load-failed, mocks-synced, stopping) as JSON to this URL; can be repeated"
+    )]
+    pub lifecycle_webhook: Vec<String>,
+
+    #[clap(
+        long = "static",
+        value_parser = crate::static_files::parse_static_route,
+        help = "serve a local directory for requests without Proxy-Host, as PREFIX=DIR or DIR (at /), can be repeated"
+    )]
+    pub static_routes: Vec<(String, std::path::PathBuf)>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "list the contents of served directories without an index.html"
+    )]
+    pub static_listing: bool,
+
+    #[clap(
+        long,
+        help = "compress mock and static file bodies of at least this many bytes with brotli or gzip, when the client accepts it"
+    )]
+    pub compress_min_size: Option<u64>,
+
+    #[clap(
+        long,
+        help = "serve connection, upstream and task gauges as JSON on this path (e.g. /_stats)"
+    )]
+    pub stats_path: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "answer requests that no mock, static file or built-in endpoint serves with --unmatched-status, and exit with code 1 if there were any"
+    )]
+    pub strict: bool,
+
+    #[clap(
+        long,
+        default_value = "501",
+        value_parser = clap::value_parser!(u16).range(400..600),
+        help = "status of unmatched requests in strict mode"
+    )]
+    pub unmatched_status: u16,
+
+    #[clap(
+        long,
+        help = "report unmatched requests as JSON on this path, with status 200 if there were none and 417 otherwise (e.g. /_verify)"
+    )]
+    pub verify_path: Option<String>,
+
+    #[clap(
+        long,
+        help = "report startup loading progress as JSON on this path, with status 200 once the JWKS, mocks and static directories have loaded and 503 until then (e.g. /readyz)"
+    )]
+    pub ready_path: Option<String>,
+
+    #[clap(
+        long,
+        help = "log a warning with a timing breakdown for requests slower than this many milliseconds"
+    )]
+    pub slow_request_threshold: Option<u64>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "simulate network round trips and bandwidth on responses, overridden per request by the X-Latency-Profile header"
+    )]
+    pub latency_profile: Option<LatencyProfile>,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "release response bodies at no more than this many bytes per second"
+    )]
+    pub bandwidth_limit: Option<u64>,
+
+    #[clap(
+        long,
+        value_parser = crate::throttle::parse_bandwidth_route,
+        help = "PREFIX=BYTES_PER_SEC bandwidth limit for paths under PREFIX, can be repeated (overrides --bandwidth-limit)"
+    )]
+    pub bandwidth_route: Vec<(String, u64)>,
+
+    #[clap(
+        long,
+        help = "bytes a throttled response may send at once (default 10ms worth, at least 1024)"
+    )]
+    pub bandwidth_burst: Option<u64>,
+
+    #[clap(
+        long,
+        help = "abort the connection after sending this many bytes of each response body, overridden per request by the X-Reset-After-Bytes header"
+    )]
+    pub reset_after_bytes: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "add a Server-Timing header with the proxy's timing breakdown to responses"
+    )]
+    pub server_timing: bool,
+
+    #[clap(
+        long,
+        help = "header to mask in logs and snapshots, can be repeated (Authorization, Proxy-Authorization, Cookie and Set-Cookie always are)"
+    )]
+    pub redact_header: Vec<String>,
+
+    #[clap(
+        long,
+        help = "dot separated JSON field path to mask in logged and recorded bodies, can be repeated (* matches any key)"
+    )]
+    pub redact_json_path: Vec<String>,
+
+    #[clap(
+        long,
+        help = "regular expression to mask in logged URIs and bodies, and in snapshots, can be repeated"
+    )]
+    pub redact_pattern: Vec<String>,
 }
 
 impl CommandFlags {
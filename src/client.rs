@@ -0,0 +1,295 @@
+use hyper_util::client::legacy::connect::{Connection, HttpInfo};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::stats;
+use crate::timing::ConnectTiming;
+
+tokio::task_local! {
+    /// Time the resolver took for the connection being opened in this task
+    static RESOLVE_TIME: Cell<Option<Duration>>;
+}
+
+/// Settings of the client for one upstream, or for all upstreams without their own
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    pub pool: PoolSettings,
+    pub ip_family: IpFamily,
+    pub bind: Option<OutboundBind>,
+    /// Send header names like `Content-Type` rather than lowercase, for case-sensitive
+    /// upstreams
+    pub title_case_headers: bool,
+}
+
+/// Local side of upstream connections
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutboundBind {
+    /// Connect from this local address
+    Address(IpAddr),
+    /// Connect through this network interface (SO_BINDTODEVICE on Linux)
+    Interface(String),
+}
+
+/// Connection pool settings for an upstream client
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct PoolSettings {
+    /// Idle connections kept per host
+    pub max_idle: Option<usize>,
+    /// Seconds before an idle connection is closed
+    pub idle_timeout: Option<u64>,
+}
+
+impl PoolSettings {
+    /// These settings, with the unset ones taken from `defaults`
+    pub fn or(self, defaults: PoolSettings) -> PoolSettings {
+        PoolSettings {
+            max_idle: self.max_idle.or(defaults.max_idle),
+            idle_timeout: self.idle_timeout.or(defaults.idle_timeout),
+        }
+    }
+}
+
+/// Address family used to connect to upstreams.
+///
+/// Connections to the second family are started 300ms after the first (happy eyeballs),
+/// so a host with a broken AAAA record costs a short delay rather than a stalled request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpFamily {
+    /// Addresses in resolver order, racing the other family
+    #[default]
+    Race,
+    /// IPv4 addresses first, racing IPv6
+    PreferIpv4,
+    /// IPv6 addresses first, racing IPv4
+    PreferIpv6,
+    /// IPv4 addresses only
+    Ipv4,
+    /// IPv6 addresses only
+    Ipv6,
+}
+
+impl IpFamily {
+    /// The addresses to try, in order
+    fn select(self, addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+        let mut addrs: Vec<SocketAddr> = match self {
+            Self::Ipv4 => addrs.filter(SocketAddr::is_ipv4).collect(),
+            Self::Ipv6 => addrs.filter(SocketAddr::is_ipv6).collect(),
+            _ => addrs.collect(),
+        };
+        match self {
+            Self::PreferIpv4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+            Self::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+            _ => {}
+        }
+        addrs
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use clap::ValueEnum;
+
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{self:?}"),
+        }
+    }
+}
+
+/// System resolver that orders or filters addresses by family, and reports how long it
+/// took to the connection being opened
+struct FamilyResolver(IpFamily);
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+
+        Box::pin(async move {
+            let started_at = Instant::now();
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await;
+            let _ = RESOLVE_TIME.try_with(|time| time.set(Some(started_at.elapsed())));
+
+            let addrs = family.select(addrs?);
+            if addrs.is_empty() {
+                return Err(format!("{} has no address for {family}", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Build the HTTP client used for upstream requests
+pub fn build_client() -> reqwest::Result<reqwest::Client> {
+    build_upstream_client(ClientOptions::default())
+}
+
+/// Build an upstream client with its own pool, address family, local address and header
+/// casing settings
+pub fn build_upstream_client(options: ClientOptions) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::ClientBuilder::new()
+        .connector_layer(TrackConnections)
+        .dns_resolver(Arc::new(FamilyResolver(options.ip_family)));
+    if let Some(max_idle) = options.pool.max_idle {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(idle_timeout) = options.pool.idle_timeout {
+        builder = builder.pool_idle_timeout(Duration::from_secs(idle_timeout));
+    }
+    if options.title_case_headers {
+        builder = builder.http1_title_case_headers();
+    }
+    match options.bind {
+        Some(OutboundBind::Address(address)) => builder = builder.local_address(address),
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+        Some(OutboundBind::Interface(interface)) => builder = builder.interface(&interface),
+        #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+        Some(OutboundBind::Interface(interface)) => {
+            log::warn!("Binding to interface {interface} is not supported on this platform")
+        }
+        None => {}
+    }
+    builder.build()
+}
+
+/// Connector layer registering every connection the client opens with the connection
+/// stats, with the time resolving and connecting took, so responses can tell new
+/// connections from reused ones and account for their setup
+#[derive(Clone)]
+struct TrackConnections;
+
+impl<S> Layer<S> for TrackConnections {
+    type Service = TrackConnectionsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TrackConnectionsService(inner)
+    }
+}
+
+#[derive(Clone)]
+struct TrackConnectionsService<S>(S);
+
+impl<S, R> Service<R> for TrackConnectionsService<S>
+where
+    S: Service<R>,
+    S::Response: Connection + Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let connecting = self.0.call(request);
+        let started_at = Instant::now();
+        Box::pin(RESOLVE_TIME.scope(Cell::new(None), async move {
+            let connection = connecting.await?;
+
+            // addresses given as IPs are not resolved
+            let dns = RESOLVE_TIME.with(Cell::get).unwrap_or_default();
+            let setup = ConnectTiming {
+                dns,
+                connect: started_at.elapsed().saturating_sub(dns),
+                ready_at: Instant::now(),
+            };
+
+            let mut extras = hyper::http::Extensions::new();
+            connection.connected().get_extras(&mut extras);
+            if let Some(info) = extras.get::<HttpInfo>() {
+                stats::UPSTREAM_CONNECTIONS.opened(info.local_addr(), setup);
+            }
+
+            Ok(connection)
+        }))
+    }
+}
+
+/// Parse an `UPSTREAM=MAX_IDLE[,IDLE_TIMEOUT]` pool setting, where either number may be
+/// left empty to use the global setting
+pub fn parse_upstream_pool(value: &str) -> Result<(String, PoolSettings), String> {
+    let Some((upstream, settings)) = value.rsplit_once('=') else {
+        return Err(format!(
+            "upstream pool must be UPSTREAM=MAX_IDLE[,IDLE_TIMEOUT]: {value}"
+        ));
+    };
+
+    let (max_idle, idle_timeout) = settings.split_once(',').unwrap_or((settings, ""));
+    let max_idle = match max_idle.trim() {
+        "" => None,
+        max_idle => Some(
+            max_idle
+                .parse::<usize>()
+                .map_err(|error| format!("invalid max idle connections {max_idle}: {error}"))?,
+        ),
+    };
+    let idle_timeout = match idle_timeout.trim() {
+        "" => None,
+        idle_timeout => Some(
+            idle_timeout
+                .parse::<u64>()
+                .map_err(|error| format!("invalid idle timeout {idle_timeout}: {error}"))?,
+        ),
+    };
+
+    Ok((
+        upstream.to_owned(),
+        PoolSettings {
+            max_idle,
+            idle_timeout,
+        },
+    ))
+}
+
+/// Parse an `UPSTREAM=FAMILY` address family setting
+pub fn parse_upstream_ip_family(value: &str) -> Result<(String, IpFamily), String> {
+    use clap::ValueEnum;
+
+    let Some((upstream, family)) = value.rsplit_once('=') else {
+        return Err(format!(
+            "upstream address family must be UPSTREAM=FAMILY: {value}"
+        ));
+    };
+
+    let family = IpFamily::from_str(family.trim(), true)?;
+    Ok((upstream.to_owned(), family))
+}
+
+/// Parse a local IP address, or else a network interface name
+pub fn parse_outbound_bind(value: &str) -> Result<OutboundBind, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("expected a local address or an interface name".to_owned());
+    }
+
+    Ok(match value.parse::<IpAddr>() {
+        Ok(address) => OutboundBind::Address(address),
+        Err(_) => OutboundBind::Interface(value.to_owned()),
+    })
+}
+
+/// Parse an `UPSTREAM=ADDRESS` or `UPSTREAM=INTERFACE` outbound binding
+pub fn parse_upstream_outbound_bind(value: &str) -> Result<(String, OutboundBind), String> {
+    let Some((upstream, bind)) = value.rsplit_once('=') else {
+        return Err(format!(
+            "upstream binding must be UPSTREAM=ADDRESS or UPSTREAM=INTERFACE: {value}"
+        ));
+    };
+
+    Ok((upstream.to_owned(), parse_outbound_bind(bind)?))
+}
@@ -0,0 +1,187 @@
+use http_body_util::BodyExt;
+use hyper::{Method, Request, Response};
+use serde_json::json;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::body::{full, ProxyBody};
+use crate::error_page::error_response;
+
+/// Offset of the virtual clock from the host clock, in seconds
+static OFFSET_SECS: AtomicI64 = AtomicI64::new(0);
+
+/// Last second of year 9999, the latest time HTTP dates and timestamps are written for
+const MAX_UNIX_SECS: u64 = 253_402_300_799;
+
+/// Shift every timestamp the proxy produces or checks (Date headers, issued tokens,
+/// token expiry, webhook signatures) by `offset_secs`, without touching the host clock.
+pub fn set_offset(offset_secs: i64) {
+    OFFSET_SECS.store(offset_secs, Ordering::Relaxed);
+}
+
+pub fn offset() -> i64 {
+    OFFSET_SECS.load(Ordering::Relaxed)
+}
+
+pub fn is_shifted() -> bool {
+    offset() != 0
+}
+
+/// Current time of the virtual clock
+pub fn now() -> SystemTime {
+    let now = SystemTime::now();
+    // offsets are checked when parsed, so this only falls back if the host clock is far off
+    shift(now, offset()).unwrap_or(now)
+}
+
+fn shift(time: SystemTime, offset_secs: i64) -> Option<SystemTime> {
+    if offset_secs >= 0 {
+        time.checked_add(Duration::from_secs(offset_secs as u64))
+    } else {
+        time.checked_sub(Duration::from_secs(offset_secs.unsigned_abs()))
+    }
+}
+
+/// Parse a clock offset in seconds, which must keep the clock between 1970 and 9999
+pub fn parse_offset(value: &str) -> Result<i64, String> {
+    let offset_secs = value
+        .trim()
+        .parse::<i64>()
+        .map_err(|error| format!("invalid clock offset {value}: {error}"))?;
+
+    shift(SystemTime::now(), offset_secs)
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .filter(|since_epoch| since_epoch.as_secs() <= MAX_UNIX_SECS)
+        .ok_or_else(|| format!("clock offset {offset_secs} moves the clock outside 1970-9999"))?;
+
+    Ok(offset_secs)
+}
+
+/// Answer with the offset and the virtual time on GET, after setting the offset to the
+/// seconds in the body on PUT
+pub async fn handle_request(request: Request<hyper::body::Incoming>) -> Response<ProxyBody> {
+    match *request.method() {
+        Method::GET => {}
+        Method::PUT => {
+            let Ok(body) = request.into_body().collect().await else {
+                return error_response(400, "Failed to read request body");
+            };
+            let offset_secs = match std::str::from_utf8(&body.to_bytes())
+                .map_err(|error| error.to_string())
+                .and_then(parse_offset)
+            {
+                Ok(offset_secs) => offset_secs,
+                Err(error) => return error_response(400, error),
+            };
+            set_offset(offset_secs);
+            log::info!("Clock offset set to {offset_secs} seconds");
+        }
+        _ => {
+            return Response::builder()
+                .status(405)
+                .header(hyper::header::ALLOW, "GET, PUT")
+                .body(full("Method Not Allowed"))
+                .unwrap()
+        }
+    }
+
+    Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(full(
+            json!({ "offset": offset(), "now": rfc3339(now()) }).to_string(),
+        ))
+        .unwrap()
+}
+
+/// Current time of the virtual clock, in seconds since the unix epoch
+pub fn unix_now() -> u64 {
+    now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// UTC timestamp like `2024-01-02T03:04:05.678Z`
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+/// Parse a timestamp like `2024-01-02T03:04:05.678Z` or `2024-01-02T03:04:05+09:00`
+pub fn parse_rfc3339(text: &str) -> Option<SystemTime> {
+    let (date, time) = text.split_once(['T', 't', ' '])?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (time, offset_secs) = match time.find(['Z', 'z', '+', '-']) {
+        Some(index) => {
+            let (time, zone) = time.split_at(index);
+            let offset_secs = match zone {
+                "Z" | "z" => 0,
+                _ => {
+                    let (hours, minutes) = zone[1..].split_once(':')?;
+                    let offset =
+                        hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+                    if zone.starts_with('-') {
+                        -offset
+                    } else {
+                        offset
+                    }
+                }
+            };
+            (time, offset_secs)
+        }
+        None => return None,
+    };
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        let digits: String = fraction
+            .chars()
+            .chain("000000000".chars())
+            .take(9)
+            .collect();
+        digits.parse::<u32>().ok()?
+    };
+
+    // days since 1970-01-01 from a civil date (Howard Hinnant's algorithm)
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset_secs;
+    let secs = u64::try_from(secs).ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
@@ -0,0 +1,256 @@
+use http_body_util::BodyExt;
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY};
+use hyper::{HeaderMap, Response, StatusCode};
+use std::io::Write;
+
+use crate::body::{full, ProxyBody};
+
+/// Brotli quality, well below the maximum so large fixtures compress quickly
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(data)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compresses locally served bodies (mocks and static files) with brotli or gzip,
+/// when the client accepts it and the body is at least `min_size` bytes.
+///
+/// Only 200 responses are compressed, as a compressed byte range could not be put together
+/// with other ranges. The ETag of a compressed body is made weak, since its bytes differ.
+pub struct ResponseCompression {
+    min_size: u64,
+}
+
+impl ResponseCompression {
+    pub fn new(min_size: u64) -> Self {
+        Self { min_size }
+    }
+
+    pub async fn apply(
+        &self,
+        response: Response<ProxyBody>,
+        request_headers: &HeaderMap,
+    ) -> Response<ProxyBody> {
+        let eligible = !response.headers().contains_key(CONTENT_ENCODING)
+            && !is_precompressed(response.headers())
+            && response.status() == StatusCode::OK
+            && hyper::body::Body::size_hint(response.body())
+                .exact()
+                .is_some_and(|size| size >= self.min_size);
+        if !eligible {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        if !varies_on(&parts.headers, "accept-encoding") {
+            parts
+                .headers
+                .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        }
+
+        let Ok(body) = body.collect().await else {
+            return Response::from_parts(parts, full(""));
+        };
+        let body = body.to_bytes();
+
+        let encoding = request_headers
+            .get_all(hyper::header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(negotiate);
+
+        let Some(encoding) = encoding else {
+            return Response::from_parts(parts, full(body));
+        };
+
+        match encoding.compress(&body) {
+            Ok(compressed) => {
+                parts
+                    .headers
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+                parts.headers.remove(CONTENT_LENGTH);
+                weaken_etag(&mut parts.headers);
+                Response::from_parts(parts, full(compressed))
+            }
+            Err(error) => {
+                log::warn!(
+                    "Failed to compress response with {}: {error}",
+                    encoding.name()
+                );
+                Response::from_parts(parts, full(body))
+            }
+        }
+    }
+}
+
+/// Whether a `Vary` header already lists the request header, or `*`
+fn varies_on(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|listed| listed == "*" || listed.eq_ignore_ascii_case(name))
+}
+
+/// `W/"tag"` for a strong `"tag"`, so caches do not take the compressed body for the
+/// identical bytes of the uncompressed one
+fn weaken_etag(headers: &mut HeaderMap) {
+    let Some(etag) = headers.get(ETAG) else {
+        return;
+    };
+    if etag.as_bytes().starts_with(b"W/") {
+        return;
+    }
+
+    let mut weak = b"W/".to_vec();
+    weak.extend_from_slice(etag.as_bytes());
+    if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+        headers.insert(ETAG, weak);
+    }
+}
+
+/// The best encoding in an Accept-Encoding value, preferring brotli on equal weights
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut weights = [(Encoding::Brotli, None), (Encoding::Gzip, None)];
+    let mut wildcard = None;
+
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let weight = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|weight| weight.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match coding.as_str() {
+            "*" => wildcard = Some(weight),
+            coding => {
+                for (encoding, encoding_weight) in weights.iter_mut() {
+                    if encoding.name() == coding {
+                        *encoding_weight = Some(weight);
+                    }
+                }
+            }
+        }
+    }
+
+    weights
+        .into_iter()
+        .filter_map(|(encoding, weight)| Some((encoding, weight.or(wildcard)?)))
+        .filter(|(_, weight)| *weight > 0.0)
+        .fold(
+            None,
+            |best: Option<(Encoding, f32)>, (encoding, weight)| match best {
+                Some((_, best_weight)) if best_weight >= weight => best,
+                _ => Some((encoding, weight)),
+            },
+        )
+        .map(|(encoding, _)| encoding)
+}
+
+/// Content types that gain nothing from another round of compression
+fn is_precompressed(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    let precompressed_media = ["image/", "video/", "audio/", "font/woff"];
+    let precompressed_types = [
+        "application/zip",
+        "application/gzip",
+        "application/x-gzip",
+        "application/x-bzip2",
+        "application/x-7z-compressed",
+        "application/pdf",
+    ];
+
+    (precompressed_media
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+        && !content_type.starts_with("image/svg"))
+        || precompressed_types
+            .iter()
+            .any(|media_type| content_type.starts_with(media_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gzip_request() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            hyper::header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip"),
+        );
+        headers
+    }
+
+    fn response(status: u16) -> Response<ProxyBody> {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .header(ETAG, "\"v1\"")
+            .header(VARY, "Accept")
+            .body(full("{}".repeat(100)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn compresses_full_responses_with_a_weak_etag() {
+        let compressed = ResponseCompression::new(10)
+            .apply(response(200), &gzip_request())
+            .await;
+
+        let headers = compressed.headers();
+        assert_eq!(headers[CONTENT_ENCODING], "gzip");
+        assert_eq!(headers[ETAG], "W/\"v1\"");
+        let vary: Vec<_> = headers.get_all(VARY).iter().collect();
+        assert_eq!(vary, ["Accept", "Accept-Encoding"]);
+    }
+
+    #[tokio::test]
+    async fn leaves_byte_ranges_alone() {
+        let partial = ResponseCompression::new(10)
+            .apply(response(206), &gzip_request())
+            .await;
+
+        let headers = partial.headers();
+        assert!(!headers.contains_key(CONTENT_ENCODING));
+        assert_eq!(headers[ETAG], "\"v1\"");
+    }
+}
@@ -1,2 +1,8 @@
 pub const PROXY_HOST_HEADER: &str = "Proxy-Host";
+pub const REQUEST_TIMEOUT_HEADER: &str = "X-Request-Timeout";
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+pub const SERVER_TIMING_HEADER: &str = "Server-Timing";
+pub const LATENCY_PROFILE_HEADER: &str = "X-Latency-Profile";
+pub const RESET_AFTER_BYTES_HEADER: &str = "X-Reset-After-Bytes";
+pub const JWT_CLAIMS_HEADER: &str = "X-Jwt-Claims";
 pub const UPGRADE_SOCKET_PATH: &str = "/tmp/affogato_upgrade.sock";
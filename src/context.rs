@@ -0,0 +1,101 @@
+use hyper::header::HeaderValue;
+use hyper::{Method, Request};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+
+use crate::clock;
+use crate::constants::PROXY_HOST_HEADER;
+use crate::latency::LatencyProfile;
+use crate::reset::reset_after_from_headers;
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What is known about a request besides the request itself.
+///
+/// Taken from the request before it is handled, so the steps after the response (logs,
+/// faults, throttling) see the same values, and filled in with the route that answered.
+pub struct RequestContext {
+    /// Sequential id, unique for the life of the process
+    pub id: u64,
+    pub received_at: Instant,
+    pub started_at: SystemTime,
+    pub client_addr: SocketAddr,
+    pub method: Method,
+    pub path: String,
+    /// Value of the `Proxy-Host` header
+    pub upstream: Option<String>,
+    pub latency_profile: Option<LatencyProfile>,
+    pub reset_after_bytes: Option<u64>,
+    pub route: Route,
+    /// Origin allowed by the preflight simulation, answered with CORS headers
+    pub cors_origin: Option<HeaderValue>,
+}
+
+impl RequestContext {
+    pub fn new<B>(
+        request: &Request<B>,
+        client_addr: SocketAddr,
+        default_latency_profile: Option<LatencyProfile>,
+        default_reset_after_bytes: Option<u64>,
+    ) -> Self {
+        let headers = request.headers();
+
+        Self {
+            id: NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+            received_at: Instant::now(),
+            started_at: clock::now(),
+            client_addr,
+            method: request.method().to_owned(),
+            path: request.uri().path().to_owned(),
+            upstream: headers
+                .get(PROXY_HOST_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            latency_profile: LatencyProfile::from_headers(headers).or(default_latency_profile),
+            reset_after_bytes: reset_after_from_headers(headers).or(default_reset_after_bytes),
+            route: Route::Rejected,
+            cors_origin: None,
+        }
+    }
+}
+
+/// What answered a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Route {
+    /// Refused before routing, e.g. while not ready or for a bad `Proxy-Host` header
+    Rejected,
+    Stats,
+    Ready,
+    Verify,
+    MockSync,
+    Clock,
+    Oidc,
+    Utility,
+    Mock,
+    Static,
+    /// Nothing matched a request without `Proxy-Host`
+    Unmatched,
+    Upstream,
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Route::Rejected => "rejected",
+            Route::Stats => "stats",
+            Route::Ready => "ready",
+            Route::Verify => "verify",
+            Route::MockSync => "mock-sync",
+            Route::Clock => "clock",
+            Route::Oidc => "oidc",
+            Route::Utility => "utility",
+            Route::Mock => "mock",
+            Route::Static => "static",
+            Route::Unmatched => "unmatched",
+            Route::Upstream => "upstream",
+        };
+        write!(f, "{name}")
+    }
+}
@@ -0,0 +1,299 @@
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Method, Request, Response, Uri};
+
+use crate::body::{full, ProxyBody};
+use crate::error_page::error_response;
+
+/// How long browsers may cache a preflight answer, in seconds
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+/// Simulated CSRF defenses and CORS preflight in front of mocks, static files and utility
+/// endpoints, so web apps' handling of them can be tested against the proxy.
+///
+/// State-changing requests (anything but GET, HEAD, OPTIONS and TRACE) are refused with
+/// 403 when their `Origin`, or the origin of their `Referer`, is not allowed, and, with a
+/// CSRF cookie, when the CSRF header does not repeat the cookie's value (double-submit).
+pub struct CsrfProtection {
+    /// Allowed origins; any origin is allowed when empty
+    origins: Vec<String>,
+    preflight: bool,
+    cookie: Option<String>,
+    header: String,
+    paths: Vec<String>,
+}
+
+impl CsrfProtection {
+    pub fn new(
+        origins: Vec<String>,
+        preflight: bool,
+        cookie: Option<String>,
+        header: &str,
+        paths: Vec<String>,
+    ) -> Self {
+        Self {
+            origins: origins
+                .into_iter()
+                .map(|origin| origin.trim_end_matches('/').to_owned())
+                .collect(),
+            preflight,
+            cookie,
+            header: header.to_ascii_lowercase(),
+            paths,
+        }
+    }
+
+    /// Without configured path prefixes, every path is protected
+    fn applies_to(&self, path: &str) -> bool {
+        self.paths.is_empty() || self.paths.iter().any(|prefix| path.starts_with(prefix))
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.is_empty() || self.origins.iter().any(|allowed| allowed == origin)
+    }
+
+    /// The answer to a preflight, or the rejection of a request; `None` lets it through
+    pub fn check<B>(&self, request: &Request<B>) -> Option<Response<ProxyBody>> {
+        if !self.applies_to(request.uri().path()) {
+            return None;
+        }
+        let headers = request.headers();
+
+        if self.preflight
+            && request.method() == Method::OPTIONS
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return Some(self.preflight_response(headers));
+        }
+
+        if matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) {
+            return None;
+        }
+
+        if !self.origins.is_empty() {
+            let Some(origin) = request_origin(headers) else {
+                return Some(error_response(
+                    403,
+                    "Forbidden: Origin and Referer headers are missing",
+                ));
+            };
+            if !self.allows(&origin) {
+                return Some(error_response(
+                    403,
+                    format!("Forbidden: origin {origin} is not allowed"),
+                ));
+            }
+        }
+
+        if let Some(cookie) = &self.cookie {
+            let expected = cookie_value(headers, cookie);
+            let token = headers
+                .get(self.header.as_str())
+                .and_then(|value| value.to_str().ok());
+            if expected.is_none() || expected != token {
+                return Some(error_response(
+                    403,
+                    format!(
+                        "Forbidden: {} header does not match the {cookie} cookie",
+                        self.header
+                    ),
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// The origin to echo in CORS headers of the response, when preflight is simulated
+    pub fn cors_origin(&self, request_path: &str, headers: &HeaderMap) -> Option<HeaderValue> {
+        if !self.preflight || !self.applies_to(request_path) {
+            return None;
+        }
+        headers
+            .get(header::ORIGIN)
+            .filter(|origin| origin.to_str().is_ok_and(|origin| self.allows(origin)))
+            .cloned()
+    }
+
+    fn preflight_response(&self, headers: &HeaderMap) -> Response<ProxyBody> {
+        let Some(origin) = headers
+            .get(header::ORIGIN)
+            .filter(|origin| origin.to_str().is_ok_and(|origin| self.allows(origin)))
+        else {
+            return error_response(403, "Forbidden: origin is not allowed");
+        };
+
+        let mut response = Response::builder()
+            .status(204)
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS",
+            )
+            .header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE)
+            .body(full(""))
+            .unwrap();
+        // any requested header is allowed, including the CSRF header
+        if let Some(requested) = headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            response
+                .headers_mut()
+                .insert(header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+        }
+        allow_origin(response.headers_mut(), origin);
+        response
+    }
+}
+
+/// Let the browser hand the response, with credentials, to a page of the origin
+pub fn allow_origin(headers: &mut HeaderMap, origin: &HeaderValue) {
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        HeaderValue::from_static("true"),
+    );
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+}
+
+/// `Origin`, or the scheme and authority of `Referer`
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        return origin.to_str().ok().map(str::to_owned);
+    }
+
+    let referer = headers
+        .get(header::REFERER)?
+        .to_str()
+        .ok()?
+        .parse::<Uri>()
+        .ok()?;
+    Some(format!(
+        "{}://{}",
+        referer.scheme_str()?,
+        referer.authority()?
+    ))
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, headers: &[(&'static str, &'static str)]) -> Request<()> {
+        let mut builder = Request::builder().method(method).uri("/api/orders");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn status(response: Option<Response<ProxyBody>>) -> Option<u16> {
+        response.map(|response| response.status().as_u16())
+    }
+
+    fn protection(cookie: Option<&str>) -> CsrfProtection {
+        CsrfProtection::new(
+            vec!["https://app.example/".to_owned()],
+            true,
+            cookie.map(str::to_owned),
+            "X-CSRF-Token",
+            vec!["/api".to_owned()],
+        )
+    }
+
+    #[test]
+    fn checks_the_origin_of_state_changing_requests() {
+        let csrf = protection(None);
+        let allowed = request(Method::POST, &[("origin", "https://app.example")]);
+        assert_eq!(status(csrf.check(&allowed)), None);
+
+        let referer = request(
+            Method::POST,
+            &[("referer", "https://app.example/checkout?step=2")],
+        );
+        assert_eq!(status(csrf.check(&referer)), None);
+
+        let other = request(Method::DELETE, &[("origin", "https://evil.example")]);
+        assert_eq!(status(csrf.check(&other)), Some(403));
+
+        assert_eq!(status(csrf.check(&request(Method::PUT, &[]))), Some(403));
+        assert_eq!(status(csrf.check(&request(Method::GET, &[]))), None);
+    }
+
+    #[test]
+    fn requires_the_header_to_repeat_the_cookie() {
+        let csrf = protection(Some("csrf"));
+        let origin = ("origin", "https://app.example");
+
+        let matching = request(
+            Method::POST,
+            &[
+                origin,
+                ("cookie", "session=1; csrf=abc"),
+                ("x-csrf-token", "abc"),
+            ],
+        );
+        assert_eq!(status(csrf.check(&matching)), None);
+
+        let mismatched = request(
+            Method::POST,
+            &[origin, ("cookie", "csrf=abc"), ("x-csrf-token", "xyz")],
+        );
+        assert_eq!(status(csrf.check(&mismatched)), Some(403));
+
+        let no_cookie = request(Method::POST, &[origin, ("x-csrf-token", "abc")]);
+        assert_eq!(status(csrf.check(&no_cookie)), Some(403));
+    }
+
+    #[test]
+    fn answers_preflights_for_allowed_origins() {
+        let csrf = protection(None);
+        let preflight = request(
+            Method::OPTIONS,
+            &[
+                ("origin", "https://app.example"),
+                ("access-control-request-method", "POST"),
+                ("access-control-request-headers", "x-csrf-token"),
+            ],
+        );
+        let response = csrf.check(&preflight).unwrap();
+        assert_eq!(response.status(), 204);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "x-csrf-token"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let other = request(
+            Method::OPTIONS,
+            &[
+                ("origin", "https://evil.example"),
+                ("access-control-request-method", "POST"),
+            ],
+        );
+        assert_eq!(status(csrf.check(&other)), Some(403));
+    }
+
+    #[test]
+    fn leaves_other_paths_alone() {
+        let csrf = protection(None);
+        let request = Request::post("/health").body(()).unwrap();
+        assert_eq!(status(csrf.check(&request)), None);
+        assert_eq!(csrf.cors_origin("/health", request.headers()), None);
+    }
+}
@@ -0,0 +1,105 @@
+use hyper::header::HeaderValue;
+use hyper::HeaderMap;
+use std::time::Duration;
+
+use crate::constants::{GRPC_TIMEOUT_HEADER, REQUEST_TIMEOUT_HEADER};
+
+/// End-to-end time budget sent by the client.
+///
+/// `X-Request-Timeout` is given in milliseconds, `grpc-timeout` follows the gRPC wire format
+/// (at most 8 digits followed by a unit: H, M, S, m, u, n).
+/// When both are present, the smaller budget wins.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    request_timeout: Option<Duration>,
+    grpc_timeout: Option<Duration>,
+}
+
+impl Deadline {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let request_timeout = headers
+            .get(REQUEST_TIMEOUT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_millis);
+
+        let grpc_timeout = headers
+            .get(GRPC_TIMEOUT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_grpc_timeout);
+
+        if request_timeout.is_none() && grpc_timeout.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            request_timeout,
+            grpc_timeout,
+        })
+    }
+
+    /// The budget left after `elapsed`, or None if it is already used up.
+    pub fn remaining(&self, elapsed: Duration) -> Option<Duration> {
+        let budget = match (self.request_timeout, self.grpc_timeout) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => return None,
+        };
+
+        budget.checked_sub(elapsed).filter(|d| !d.is_zero())
+    }
+
+    /// Rewrite the timeout headers so the upstream sees the decremented budget.
+    pub fn write_headers(&self, headers: &mut HeaderMap, elapsed: Duration) {
+        if let Some(timeout) = self.request_timeout {
+            let remaining = timeout.saturating_sub(elapsed);
+            headers.insert(
+                REQUEST_TIMEOUT_HEADER,
+                HeaderValue::from(remaining.as_millis() as u64),
+            );
+        }
+
+        if let Some(timeout) = self.grpc_timeout {
+            let remaining = timeout.saturating_sub(elapsed);
+            if let Ok(value) = HeaderValue::from_str(&format_grpc_timeout(remaining)) {
+                headers.insert(GRPC_TIMEOUT_HEADER, value);
+            }
+        }
+    }
+}
+
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount = amount.parse::<u64>().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+fn format_grpc_timeout(timeout: Duration) -> String {
+    const MAX_AMOUNT: u128 = 99_999_999;
+
+    let micros = timeout.as_micros();
+    if micros <= MAX_AMOUNT {
+        return format!("{micros}u");
+    }
+
+    let millis = timeout.as_millis();
+    if millis <= MAX_AMOUNT {
+        return format!("{millis}m");
+    }
+
+    format!("{}S", timeout.as_secs().min(MAX_AMOUNT as u64))
+}
@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+const RCODE_FORMERR: u8 = 1;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_NOTIMP: u8 = 4;
+const RCODE_REFUSED: u8 = 5;
+
+/// How a name should fail
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DnsFailure {
+    Nxdomain,
+    Servfail,
+    Refused,
+    /// never answer, so the client times out
+    Timeout,
+}
+
+/// Mock DNS responder over UDP and TCP.
+///
+/// Answers A and AAAA queries from configured records, with `*.` names matching any
+/// subdomain. Unknown names get NXDOMAIN, and names can be set to fail on purpose.
+pub struct DnsServer {
+    records: Vec<(String, IpAddr)>,
+    failures: Vec<(String, DnsFailure)>,
+    ttl: u32,
+}
+
+struct Question<'a> {
+    name: String,
+    qtype: u16,
+    /// the question section as received, echoed back in the response
+    raw: &'a [u8],
+}
+
+impl DnsServer {
+    pub fn new(
+        records: Vec<(String, IpAddr)>,
+        failures: Vec<(String, DnsFailure)>,
+        ttl: u32,
+    ) -> Self {
+        let normalize = |name: String| name.trim_end_matches('.').to_ascii_lowercase();
+
+        Self {
+            records: records
+                .into_iter()
+                .map(|(name, address)| (normalize(name), address))
+                .collect(),
+            failures: failures
+                .into_iter()
+                .map(|(name, failure)| (normalize(name), failure))
+                .collect(),
+            ttl,
+        }
+    }
+
+    /// Serve UDP and TCP on the address until the process exits
+    pub async fn serve(self: Arc<Self>, address: SocketAddr) -> std::io::Result<()> {
+        let udp = UdpSocket::bind(address).await?;
+        let tcp = TcpListener::bind(address).await?;
+        log::info!("DNS listening on {address} (udp, tcp)");
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            loop {
+                let Ok((length, peer)) = udp.recv_from(&mut buffer).await else {
+                    continue;
+                };
+                if let Some(response) = server.answer(&buffer[..length]) {
+                    let _ = udp.send_to(&response, peer).await;
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = tcp.accept().await else {
+                    continue;
+                };
+                let server = self.clone();
+                tokio::spawn(async move {
+                    // messages are prefixed with their length over TCP
+                    loop {
+                        let Ok(length) = stream.read_u16().await else {
+                            return;
+                        };
+                        let mut query = vec![0u8; length as usize];
+                        if stream.read_exact(&mut query).await.is_err() {
+                            return;
+                        }
+                        let Some(response) = server.answer(&query) else {
+                            continue;
+                        };
+                        let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+                        framed.extend_from_slice(&response);
+                        if stream.write_all(&framed).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Response to a query, or None to stay silent
+    fn answer(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < 12 {
+            return None;
+        }
+
+        let opcode = (query[2] >> 3) & 0x0f;
+        if opcode != 0 {
+            return Some(response_header(query, RCODE_NOTIMP, 0, 0));
+        }
+
+        let Some(question) = parse_question(query) else {
+            return Some(response_header(query, RCODE_FORMERR, 0, 0));
+        };
+
+        if let Some(failure) = lookup(&self.failures, &question.name) {
+            log::debug!("DNS {} failing with {failure:?}", question.name);
+            let rcode = match failure {
+                DnsFailure::Nxdomain => RCODE_NXDOMAIN,
+                DnsFailure::Servfail => RCODE_SERVFAIL,
+                DnsFailure::Refused => RCODE_REFUSED,
+                DnsFailure::Timeout => return None,
+            };
+            return Some(with_question(
+                response_header(query, rcode, 1, 0),
+                &question,
+            ));
+        }
+
+        let addresses: Vec<IpAddr> = self
+            .records
+            .iter()
+            .filter(|(name, _)| matches_name(name, &question.name))
+            .map(|(_, address)| *address)
+            .collect();
+
+        if addresses.is_empty() {
+            log::debug!("DNS {} not found", question.name);
+            return Some(with_question(
+                response_header(query, RCODE_NXDOMAIN, 1, 0),
+                &question,
+            ));
+        }
+
+        let answers: Vec<IpAddr> = addresses
+            .into_iter()
+            .filter(|address| match question.qtype {
+                TYPE_A => address.is_ipv4(),
+                TYPE_AAAA => address.is_ipv6(),
+                TYPE_ANY => true,
+                _ => false,
+            })
+            .collect();
+
+        log::debug!("DNS {} answered with {answers:?}", question.name);
+
+        let mut response = with_question(
+            response_header(query, 0, 1, answers.len() as u16),
+            &question,
+        );
+        for address in answers {
+            // the name is a pointer to the question at offset 12
+            response.extend_from_slice(&[0xc0, 0x0c]);
+            let (rtype, rdata) = match address {
+                IpAddr::V4(address) => (TYPE_A, address.octets().to_vec()),
+                IpAddr::V6(address) => (TYPE_AAAA, address.octets().to_vec()),
+            };
+            response.extend_from_slice(&rtype.to_be_bytes());
+            response.extend_from_slice(&CLASS_IN.to_be_bytes());
+            response.extend_from_slice(&self.ttl.to_be_bytes());
+            response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            response.extend_from_slice(&rdata);
+        }
+
+        Some(response)
+    }
+}
+
+/// Header echoing the query id and recursion flag, as an authoritative answer
+fn response_header(query: &[u8], rcode: u8, questions: u16, answers: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(512);
+    header.extend_from_slice(&query[..2]);
+    // QR, opcode and RD from the query, AA
+    header.push(0x80 | (query[2] & 0x79) | 0x04);
+    header.push(rcode);
+    header.extend_from_slice(&questions.to_be_bytes());
+    header.extend_from_slice(&answers.to_be_bytes());
+    header.extend_from_slice(&[0, 0, 0, 0]);
+    header
+}
+
+fn with_question(mut response: Vec<u8>, question: &Question) -> Vec<u8> {
+    response.extend_from_slice(question.raw);
+    response
+}
+
+fn parse_question(query: &[u8]) -> Option<Question<'_>> {
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    if questions == 0 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    loop {
+        let length = *query.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        // compression is not expected in questions
+        if length > 63 {
+            return None;
+        }
+        let label = query.get(offset..offset + length)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += length;
+    }
+
+    let fixed = query.get(offset..offset + 4)?;
+    let qtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+
+    Some(Question {
+        name: labels.join("."),
+        qtype,
+        raw: &query[12..offset + 4],
+    })
+}
+
+fn matches_name(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => name
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => pattern == name,
+    }
+}
+
+fn lookup<T: Copy>(entries: &[(String, T)], name: &str) -> Option<T> {
+    entries
+        .iter()
+        .find(|(pattern, _)| matches_name(pattern, name))
+        .map(|(_, value)| *value)
+}
+
+/// Parse a `NAME=ADDRESS` record
+pub fn parse_record(record: &str) -> Result<(String, IpAddr), String> {
+    let Some((name, address)) = record.split_once('=') else {
+        return Err(format!("record must be NAME=ADDRESS: {record}"));
+    };
+    let address = address
+        .parse()
+        .map_err(|_| format!("invalid IP address: {address}"))?;
+
+    Ok((name.to_owned(), address))
+}
+
+/// Parse a `NAME=FAILURE` failure mode
+pub fn parse_failure(failure: &str) -> Result<(String, DnsFailure), String> {
+    use clap::ValueEnum;
+
+    let Some((name, mode)) = failure.split_once('=') else {
+        return Err(format!("failure must be NAME=MODE: {failure}"));
+    };
+    let mode = DnsFailure::from_str(mode, true)?;
+
+    Ok((name.to_owned(), mode))
+}
@@ -0,0 +1,128 @@
+use hyper::header::HeaderValue;
+use hyper::{Response, StatusCode};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::body::{full, ProxyBody};
+
+/// Marks a response generated by the proxy itself, carrying its error message
+#[derive(Clone, Debug)]
+pub struct ProxyError(pub String);
+
+/// Plain-text error response generated by the proxy
+pub fn error_response(status: u16, message: impl Into<String>) -> Response<ProxyBody> {
+    let message = message.into();
+
+    let mut response = Response::builder()
+        .status(status)
+        .body(full(message.clone()))
+        .unwrap();
+    response.extensions_mut().insert(ProxyError(message));
+    response
+}
+
+/// Templated bodies replacing the plain-text errors generated by the proxy, per status.
+///
+/// Templates may use `{{status}}`, `{{reason}}` and `{{message}}`. The content type follows
+/// the template's file extension. The message is escaped for JSON templates, and
+/// HTML-escaped for the others, as it may quote the request.
+pub struct ErrorPages {
+    pages: HashMap<u16, ErrorPage>,
+}
+
+struct ErrorPage {
+    template: String,
+    content_type: String,
+}
+
+impl ErrorPages {
+    pub fn load(pages: &[(u16, PathBuf)]) -> std::io::Result<Self> {
+        let pages = pages
+            .iter()
+            .map(|(status, path)| {
+                let page = ErrorPage {
+                    template: std::fs::read_to_string(path)?,
+                    content_type: mime_guess::from_path(path)
+                        .first_or_text_plain()
+                        .to_string(),
+                };
+                Ok((*status, page))
+            })
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(Self { pages })
+    }
+
+    /// Replace the body of a proxy-generated error with its page, if one is configured
+    pub fn apply(&self, response: &mut Response<ProxyBody>) {
+        let Some(ProxyError(message)) = response.extensions().get::<ProxyError>() else {
+            return;
+        };
+        let status = response.status();
+        let Some(page) = self.pages.get(&status.as_u16()) else {
+            return;
+        };
+
+        let body = page.render(status, message);
+        if let Ok(content_type) = HeaderValue::from_str(&page.content_type) {
+            response
+                .headers_mut()
+                .insert(hyper::header::CONTENT_TYPE, content_type);
+        }
+        *response.body_mut() = full(body);
+    }
+}
+
+impl ErrorPage {
+    fn render(&self, status: StatusCode, message: &str) -> String {
+        let reason = status.canonical_reason().unwrap_or("");
+        let (reason, message) = if self.content_type.ends_with("json") {
+            (json_escape(reason), json_escape(message))
+        } else {
+            // the message may quote the request, which must not become markup
+            (html_escape(reason), html_escape(message))
+        };
+
+        self.template
+            .replace("{{status}}", status.as_str())
+            .replace("{{reason}}", &reason)
+            .replace("{{message}}", &message)
+    }
+}
+
+/// Contents of a JSON string literal, without the quotes
+fn json_escape(text: &str) -> String {
+    let quoted = serde_json::Value::from(text).to_string();
+    quoted[1..quoted.len() - 1].to_owned()
+}
+
+/// Text with the HTML special characters replaced by entities
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Parse a `STATUS=FILE` error page
+pub fn parse_error_page(page: &str) -> Result<(u16, PathBuf), String> {
+    let Some((status, path)) = page.split_once('=') else {
+        return Err(format!("error page must be STATUS=FILE: {page}"));
+    };
+
+    let status = status
+        .parse::<u16>()
+        .ok()
+        .filter(|status| (400..600).contains(status))
+        .ok_or_else(|| format!("error page status must be 400-599: {status}"))?;
+
+    Ok((status, PathBuf::from(path)))
+}
@@ -0,0 +1,841 @@
+use hyper::{Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::body::{full, ProxyBody};
+use crate::clock;
+use crate::error_page::error_response;
+use crate::header_profile::{parse_header_route, NamedProfiles};
+use crate::secret::{self, Reference};
+
+/// Number of recent unmatched requests kept for the stats
+const NEAR_MISS_CAPACITY: usize = 32;
+
+/// Repository metadata of a synced mock directory, never served
+const GIT_DIR: &str = ".git";
+
+/// `FILE=PROFILE[,PROFILE...]` lines naming the header profiles of the mock files in its
+/// directory, `*` for all of them
+const PROFILES_FILE: &str = ".profiles";
+
+/// Methods that mock file names may start with in any case; other methods, like
+/// `PROPFIND`, are written in uppercase
+const STANDARD_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::PATCH,
+    Method::OPTIONS,
+    Method::CONNECT,
+    Method::TRACE,
+];
+
+/// Mocks defined by a directory layout, for requests without `Proxy-Host`.
+///
+/// The URL path selects a directory and the method selects a file in it, so
+/// `api/users/GET.json` answers `GET /api/users`. The file name may carry a status
+/// (`POST.201.json`), and a directory named like `[id]` matches any single path segment.
+/// Files for the same method with different extensions (`GET.json`, `GET.xml`) are
+/// variants chosen by the `Accept` header.
+/// Files are read on every request, so edits apply immediately.
+/// Hits are counted per file, to find mocks that are never used, and the most recent
+/// unmatched requests are kept with the reason they missed. Files may be expected to get a
+/// number of hits, checked by `violations`, and may be limited to a number of hits, after
+/// which they are skipped as if they were gone.
+/// With environment interpolation, `${env:NAME}` in mock files is replaced with the
+/// variable, so one mock set can serve several environments.
+pub struct FsMocks {
+    dir: PathBuf,
+    interpolate_env: bool,
+    expectations: Vec<Expectation>,
+    limits: HashMap<PathBuf, u64>,
+    hits: std::sync::Mutex<HashMap<PathBuf, MockHits>>,
+    near_misses: std::sync::Mutex<VecDeque<Value>>,
+}
+
+struct MockHits {
+    count: u64,
+    last_hit: SystemTime,
+}
+
+/// Number of hits a mock file is expected to get
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Expectation {
+    file: PathBuf,
+    bound: Bound,
+    count: u64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Bound {
+    Exactly,
+    AtLeast,
+    AtMost,
+}
+
+impl Expectation {
+    fn is_met(&self, hits: u64) -> bool {
+        match self.bound {
+            Bound::Exactly => hits == self.count,
+            Bound::AtLeast => hits >= self.count,
+            Bound::AtMost => hits <= self.count,
+        }
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bound = match self.bound {
+            Bound::Exactly => "exactly",
+            Bound::AtLeast => "at least",
+            Bound::AtMost => "at most",
+        };
+        write!(f, "{bound} {}", self.count)
+    }
+}
+
+/// Parse a `FILE=N` hit limit, with FILE relative to the mock directory
+pub fn parse_limit(limit: &str) -> Result<(PathBuf, u64), String> {
+    let Some((file, count)) = limit.split_once('=') else {
+        return Err(format!("limit must be FILE=N: {limit}"));
+    };
+
+    let count = count
+        .trim()
+        .parse::<u64>()
+        .map_err(|error| format!("invalid limit {count}: {error}"))?;
+
+    Ok((PathBuf::from(file.trim().trim_start_matches('/')), count))
+}
+
+/// Parse a `FILE=N`, `FILE>=N` or `FILE<=N` expectation, with FILE relative to the mock
+/// directory
+pub fn parse_expectation(expectation: &str) -> Result<Expectation, String> {
+    let (file, bound, count) = if let Some((file, count)) = expectation.split_once(">=") {
+        (file, Bound::AtLeast, count)
+    } else if let Some((file, count)) = expectation.split_once("<=") {
+        (file, Bound::AtMost, count)
+    } else if let Some((file, count)) = expectation.split_once('=') {
+        (file, Bound::Exactly, count)
+    } else {
+        return Err(format!(
+            "expectation must be FILE=N, FILE>=N or FILE<=N: {expectation}"
+        ));
+    };
+
+    let count = count
+        .trim()
+        .parse::<u64>()
+        .map_err(|error| format!("invalid count {count}: {error}"))?;
+
+    Ok(Expectation {
+        file: PathBuf::from(file.trim().trim_start_matches('/')),
+        bound,
+        count,
+    })
+}
+
+/// A mock response file for a method
+struct MockFile {
+    path: PathBuf,
+    status: StatusCode,
+    content_type: mime_guess::Mime,
+}
+
+impl FsMocks {
+    pub fn new(
+        dir: PathBuf,
+        expectations: Vec<Expectation>,
+        limits: &[(PathBuf, u64)],
+        interpolate_env: bool,
+    ) -> Self {
+        let limits = limits
+            .iter()
+            .map(|(file, limit)| (dir.join(file), *limit))
+            .collect();
+
+        Self {
+            dir,
+            interpolate_env,
+            expectations,
+            limits,
+            hits: std::sync::Mutex::new(HashMap::new()),
+            near_misses: std::sync::Mutex::new(VecDeque::with_capacity(NEAR_MISS_CAPACITY)),
+        }
+    }
+
+    /// Hit count and last hit of every mock file (unused ones included, sorted by path),
+    /// and the recent unmatched requests
+    pub async fn stats(&self) -> Value {
+        let files = mock_files(&self.dir).await;
+
+        let hits = self.hits.lock().unwrap();
+        let usage: Vec<Value> = files
+            .iter()
+            .map(|path| {
+                let hits = hits.get(path);
+                json!({
+                    "file": path.strip_prefix(&self.dir).unwrap_or(path),
+                    "hits": hits.map_or(0, |hits| hits.count),
+                    "last_hit": hits.map(|hits| clock::rfc3339(hits.last_hit)),
+                    "limit": self.limits.get(path),
+                })
+            })
+            .collect();
+
+        json!({ "files": usage, "unmatched": self.near_misses() })
+    }
+
+    /// Read every mock file once, failing on a missing directory. Files that cannot be
+    /// read, interpolated or parsed as JSON are logged, as they only fail their own
+    /// requests. Returns the number of mock files and of invalid ones.
+    pub async fn load(&self) -> Result<(usize, usize), String> {
+        if !is_dir(&self.dir).await {
+            return Err(format!("{} is not a directory", self.dir.display()));
+        }
+
+        let files = mock_files(&self.dir).await;
+        let mut invalid = 0;
+        for path in &files {
+            if let Err(error) = self.check_file(path).await {
+                log::warn!("Invalid mock file {}: {error}", self.display(path));
+                invalid += 1;
+            }
+        }
+
+        Ok((files.len(), invalid))
+    }
+
+    async fn check_file(&self, path: &Path) -> Result<(), String> {
+        let contents = tokio::fs::read(path)
+            .await
+            .map_err(|error| error.to_string())?;
+        let contents = if self.interpolate_env {
+            interpolate_env(&contents)?
+        } else {
+            contents
+        };
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            serde_json::from_slice::<Value>(&contents).map_err(|error| error.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Expectations not met by the hits so far
+    pub fn violations(&self) -> Vec<Value> {
+        let hits = self.hits.lock().unwrap();
+
+        self.expectations
+            .iter()
+            .filter_map(|expectation| {
+                let count = hits
+                    .get(&self.dir.join(&expectation.file))
+                    .map_or(0, |hits| hits.count);
+                (!expectation.is_met(count)).then(|| {
+                    json!({
+                        "file": expectation.file,
+                        "expected": expectation.to_string(),
+                        "hits": count,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// The recent unmatched requests, oldest first
+    pub fn near_misses(&self) -> Value {
+        Value::Array(self.near_misses.lock().unwrap().iter().cloned().collect())
+    }
+
+    /// Explain why no mock file answers the request, and keep it as a near miss
+    pub async fn explain_miss<B>(&self, request: &Request<B>) -> String {
+        let path = request.uri().path();
+        let reason = self.find_miss(request.method(), path).await;
+
+        let mut near_misses = self.near_misses.lock().unwrap();
+        if near_misses.len() >= NEAR_MISS_CAPACITY {
+            near_misses.pop_front();
+        }
+        near_misses.push_back(json!({
+            "time": clock::rfc3339(clock::now()),
+            "method": request.method().as_str(),
+            "path": path,
+            "reason": reason,
+        }));
+
+        reason
+    }
+
+    /// Where the lookup of `find_dir` and `method_files` stops
+    async fn find_miss(&self, method: &Method, path: &str) -> String {
+        let dir = match find_dir(&self.dir, path).await {
+            Ok(dir) => dir,
+            Err(DirMiss::NotUtf8(segment)) => {
+                return format!("path segment {segment:?} is not valid UTF-8")
+            }
+            Err(DirMiss::NotAllowed(segment)) => {
+                return format!("path segment {segment:?} is not allowed")
+            }
+            Err(DirMiss::NoDirectory { segment, parent }) => {
+                return format!(
+                    "no directory {segment:?} or [param] directory in {}",
+                    self.display(&parent)
+                )
+            }
+        };
+
+        let mut names = Vec::new();
+        for file in method_files(&dir, None).await {
+            if let Some(name) = file.path.file_name() {
+                let name = name.to_string_lossy();
+                if self.is_used_up(&file.path) {
+                    names.push(format!("{name} (used up)"));
+                } else {
+                    names.push(name.into_owned());
+                }
+            }
+        }
+
+        if names.is_empty() {
+            format!("no mock files in {}", self.display(&dir))
+        } else {
+            format!(
+                "no {method} file in {}, it has {}",
+                self.display(&dir),
+                names.join(", ")
+            )
+        }
+    }
+
+    /// A directory relative to the mock root, like /api/users
+    fn display(&self, dir: &Path) -> String {
+        let relative = dir.strip_prefix(&self.dir).unwrap_or(dir);
+        format!("/{}", relative.to_string_lossy())
+    }
+
+    /// None if no mock file matches the request
+    pub async fn handle_request<B>(&self, request: &Request<B>) -> Option<Response<ProxyBody>> {
+        let dir = find_dir(&self.dir, request.uri().path()).await.ok()?;
+
+        let mut files = method_files(&dir, Some(request.method())).await;
+        if files.is_empty() && request.method() == Method::HEAD {
+            files = method_files(&dir, Some(&Method::GET)).await;
+        }
+        files.retain(|file| !self.is_used_up(&file.path));
+
+        loop {
+            let has_variants = files
+                .iter()
+                .any(|file| file.content_type != files[0].content_type);
+
+            let file = if has_variants {
+                let accept = request
+                    .headers()
+                    .get(hyper::header::ACCEPT)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("*/*");
+
+                match negotiate(&files, accept) {
+                    Some(file) => file,
+                    None => return Some(not_acceptable(&files)),
+                }
+            } else {
+                files.first()?
+            };
+
+            let Ok(mut contents) = tokio::fs::read(&file.path).await else {
+                return None;
+            };
+            if self.interpolate_env {
+                contents = match interpolate_env(&contents) {
+                    Ok(contents) => contents,
+                    Err(error) => {
+                        return Some(error_response(
+                            500,
+                            format!("{}: {error}", self.display(&file.path)),
+                        ))
+                    }
+                };
+            }
+
+            // a concurrent request may have taken the last hit since the files were listed,
+            // and then the next file answers
+            if !self.claim_hit(&file.path) {
+                let used_up = file.path.clone();
+                files.retain(|file| file.path != used_up);
+                continue;
+            }
+
+            let mut response = Response::builder()
+                .status(file.status)
+                .header(hyper::header::CONTENT_TYPE, file.content_type.as_ref());
+            if has_variants {
+                response = response.header(hyper::header::VARY, "Accept");
+            }
+            let profiles = named_profiles(&file.path).await;
+            if !profiles.is_empty() {
+                response = response.extension(NamedProfiles(profiles));
+            }
+
+            return Some(response.body(full(contents)).unwrap());
+        }
+    }
+
+    /// Count a hit on the file, unless it has no hits left
+    fn claim_hit(&self, path: &Path) -> bool {
+        let mut hits = self.hits.lock().unwrap();
+        let hits = hits.entry(path.to_owned()).or_insert(MockHits {
+            count: 0,
+            last_hit: SystemTime::UNIX_EPOCH,
+        });
+
+        if self
+            .limits
+            .get(path)
+            .is_some_and(|limit| hits.count >= *limit)
+        {
+            return false;
+        }
+
+        hits.count += 1;
+        hits.last_hit = clock::now();
+        true
+    }
+
+    fn is_used_up(&self, path: &Path) -> bool {
+        let Some(limit) = self.limits.get(path) else {
+            return false;
+        };
+        let hits = self.hits.lock().unwrap();
+        hits.get(path).is_some_and(|hits| hits.count >= *limit)
+    }
+}
+
+/// Header profiles the profiles file of its directory names for a mock file
+async fn named_profiles(path: &Path) -> Vec<String> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let Ok(lines) = tokio::fs::read_to_string(dir.join(PROFILES_FILE)).await else {
+        return Vec::new();
+    };
+
+    let name = name.to_string_lossy();
+    lines
+        .lines()
+        .filter_map(|line| parse_header_route(line.trim()).ok())
+        .filter(|(file, _)| file == "*" || *file == name)
+        .flat_map(|(_, profiles)| profiles)
+        .filter(|profile| !profile.is_empty())
+        .collect()
+}
+
+/// The variant the client weighs highest, the first file on ties.
+/// None if the client accepts none of them.
+fn negotiate<'a>(files: &'a [MockFile], accept: &str) -> Option<&'a MockFile> {
+    files
+        .iter()
+        .map(|file| (file, quality(accept, &file.content_type)))
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold(
+            None,
+            |best: Option<(&MockFile, f32)>, (file, quality)| match best {
+                Some((_, best_quality)) if best_quality >= quality => best,
+                _ => Some((file, quality)),
+            },
+        )
+        .map(|(file, _)| file)
+}
+
+/// Weight of the media type in an Accept value, from its most specific matching range
+fn quality(accept: &str, content_type: &mime_guess::Mime) -> f32 {
+    let (content_type, content_subtype) = normalize_media_type(
+        content_type.type_().as_str(),
+        content_type.subtype().as_str(),
+    );
+    let mut best: Option<(u8, f32)> = None;
+
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media_range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let weight = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|weight| weight.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let Some((range_type, range_subtype)) = media_range.split_once('/') else {
+            continue;
+        };
+        let (range_type, range_subtype) = normalize_media_type(range_type, range_subtype);
+        let specificity = match (range_type, range_subtype) {
+            ("*", "*") => 0,
+            (range_type, "*") if range_type == content_type => 1,
+            (range_type, range_subtype)
+                if range_type == content_type && range_subtype == content_subtype =>
+            {
+                2
+            }
+            _ => continue,
+        };
+
+        if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+            best = Some((specificity, weight));
+        }
+    }
+
+    best.map_or(0.0, |(_, weight)| weight)
+}
+
+/// text/xml and application/xml are used interchangeably
+fn normalize_media_type<'a>(media_type: &'a str, subtype: &'a str) -> (&'a str, &'a str) {
+    match (media_type, subtype) {
+        ("text", "xml") => ("application", "xml"),
+        other => other,
+    }
+}
+
+/// Replace `${env:NAME}` with the environment variable. Other `${...}` are left alone, as
+/// they may belong to the mock's own format.
+fn interpolate_env(contents: &[u8]) -> Result<Vec<u8>, String> {
+    secret::resolve_references(contents, &[Reference::Env], false)
+}
+
+fn not_acceptable(files: &[MockFile]) -> Response<ProxyBody> {
+    let available: Vec<&str> = files
+        .iter()
+        .map(|file| file.content_type.as_ref())
+        .collect();
+
+    Response::builder()
+        .status(406)
+        .header(hyper::header::VARY, "Accept")
+        .body(full(format!(
+            "Not acceptable, available: {}",
+            available.join(", ")
+        )))
+        .unwrap()
+}
+
+/// Where the lookup of the directory for a URL path stopped
+enum DirMiss {
+    /// The raw segment does not decode to UTF-8
+    NotUtf8(String),
+    /// The decoded segment would leave the directory, or names the git directory
+    NotAllowed(String),
+    /// Neither the segment nor a `[param]` directory exists in the parent
+    NoDirectory { segment: String, parent: PathBuf },
+}
+
+/// Directory for the URL path, preferring exact names over `[param]` directories
+async fn find_dir(root: &Path, path: &str) -> Result<PathBuf, DirMiss> {
+    let mut dir = root.to_owned();
+
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let Ok(decoded) = percent_encoding::percent_decode_str(segment).decode_utf8() else {
+            return Err(DirMiss::NotUtf8(segment.to_owned()));
+        };
+        if decoded == "."
+            || decoded == ".."
+            || decoded == GIT_DIR
+            || decoded.contains(['/', '\\', '\0'])
+        {
+            return Err(DirMiss::NotAllowed(decoded.into_owned()));
+        }
+
+        let exact = dir.join(decoded.as_ref());
+        if is_dir(&exact).await {
+            dir = exact;
+            continue;
+        }
+
+        dir = param_dir(&dir).await.ok_or_else(|| DirMiss::NoDirectory {
+            segment: decoded.into_owned(),
+            parent: dir.clone(),
+        })?;
+    }
+
+    Ok(dir)
+}
+
+/// The first `[param]` directory, by name
+async fn param_dir(dir: &Path) -> Option<PathBuf> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    let mut params = Vec::new();
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('[') && name.ends_with(']') && is_dir(&entry.path()).await {
+            params.push(entry.path());
+        }
+    }
+
+    params.sort();
+    params.into_iter().next()
+}
+
+/// Every mock file under the directory, sorted by path
+async fn mock_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let name = entry.file_name();
+            if file_type.is_dir() {
+                if name != GIT_DIR {
+                    dirs.push(entry.path());
+                }
+            } else if file_type.is_file() && file_method(&name.to_string_lossy()).is_some() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Files for the method, or for any method, in the directory, sorted by name
+async fn method_files(dir: &Path, method: Option<&Method>) -> Vec<MockFile> {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+
+    let mut files = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let Some(status) = parse_file_name(&name.to_string_lossy(), method) else {
+            continue;
+        };
+        if entry
+            .file_type()
+            .await
+            .is_ok_and(|file_type| file_type.is_file())
+        {
+            files.push(MockFile {
+                content_type: mime_guess::from_path(entry.path()).first_or_octet_stream(),
+                path: entry.path(),
+                status,
+            });
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// The method a file name starts with
+fn file_method(name: &str) -> Option<Method> {
+    let method = name.split('.').next()?;
+
+    if let Some(standard) = STANDARD_METHODS
+        .iter()
+        .find(|standard| standard.as_str().eq_ignore_ascii_case(method))
+    {
+        return Some(standard.clone());
+    }
+
+    if method.is_empty() || !method.bytes().all(|byte| byte.is_ascii_uppercase()) {
+        return None;
+    }
+    Method::from_bytes(method.as_bytes()).ok()
+}
+
+/// Status of a file for the method, or for any method
+fn parse_file_name(name: &str, method: Option<&Method>) -> Option<StatusCode> {
+    let file_method = file_method(name)?;
+    if method.is_some_and(|method| *method != file_method) {
+        return None;
+    }
+
+    match name.split('.').nth(1) {
+        Some(part) if part.len() == 3 && part.bytes().all(|b| b.is_ascii_digit()) => {
+            StatusCode::from_bytes(part.as_bytes()).ok()
+        }
+        _ => Some(StatusCode::OK),
+    }
+}
+
+async fn is_dir(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants(names: &[&str]) -> Vec<MockFile> {
+        names
+            .iter()
+            .map(|name| MockFile {
+                path: PathBuf::from(name),
+                status: StatusCode::OK,
+                content_type: mime_guess::from_path(name).first_or_octet_stream(),
+            })
+            .collect()
+    }
+
+    fn negotiated(files: &[MockFile], accept: &str) -> Option<String> {
+        negotiate(files, accept).map(|file| file.path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn picks_the_variant_the_client_weighs_highest() {
+        let files = variants(&["GET.json", "GET.xml", "GET.html"]);
+        assert_eq!(
+            negotiated(&files, "application/xml").as_deref(),
+            Some("GET.xml")
+        );
+        assert_eq!(
+            negotiated(&files, "text/html;q=0.5, application/json;q=0.9").as_deref(),
+            Some("GET.json")
+        );
+        assert_eq!(negotiated(&files, "*/*").as_deref(), Some("GET.json"));
+        assert_eq!(negotiated(&files, "image/png"), None);
+    }
+
+    #[test]
+    fn prefers_specific_ranges_and_treats_xml_types_alike() {
+        let files = variants(&["GET.json", "GET.xml"]);
+        assert_eq!(
+            negotiated(&files, "application/json;q=0, */*").as_deref(),
+            Some("GET.xml")
+        );
+        assert_eq!(negotiated(&files, "text/xml").as_deref(), Some("GET.xml"));
+        assert_eq!(
+            negotiated(&files, "application/*;q=0.2, application/xml").as_deref(),
+            Some("GET.xml")
+        );
+    }
+
+    /// A fresh mock directory with the files
+    fn mock_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("affogato-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (file, contents) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    async fn status(mocks: &FsMocks, method: Method, path: &str) -> Option<u16> {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap();
+        let response = mocks.handle_request(&request).await?;
+        Some(response.status().as_u16())
+    }
+
+    #[test]
+    fn parses_limits_and_expectations() {
+        assert_eq!(
+            parse_limit("/api/POST.201.json=2").unwrap(),
+            (PathBuf::from("api/POST.201.json"), 2)
+        );
+        assert!(parse_limit("api/POST.json").is_err());
+
+        let expectation = parse_expectation("api/GET.json>=3").unwrap();
+        assert!(!expectation.is_met(2));
+        assert!(expectation.is_met(4));
+        assert!(parse_expectation("api/GET.json<=1").unwrap().is_met(0));
+        assert!(!parse_expectation("api/GET.json=1").unwrap().is_met(2));
+    }
+
+    #[tokio::test]
+    async fn serves_the_next_file_once_one_is_used_up() {
+        let dir = mock_dir(
+            "limits",
+            &[("api/POST.201.json", "{}"), ("api/POST.409.json", "{}")],
+        );
+        let mocks = FsMocks::new(
+            dir.clone(),
+            Vec::new(),
+            &[parse_limit("api/POST.201.json=1").unwrap()],
+            false,
+        );
+
+        assert_eq!(status(&mocks, Method::POST, "/api").await, Some(201));
+        assert_eq!(status(&mocks, Method::POST, "/api").await, Some(409));
+        assert_eq!(status(&mocks, Method::POST, "/api").await, Some(409));
+        assert!(mocks
+            .find_miss(&Method::GET, "/api")
+            .await
+            .contains("POST.201.json (used up)"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_a_limited_file_once_under_concurrency() {
+        let dir = mock_dir(
+            "concurrent-limits",
+            &[("api/POST.201.json", "{}"), ("api/POST.409.json", "{}")],
+        );
+        let mocks = std::sync::Arc::new(FsMocks::new(
+            dir.clone(),
+            Vec::new(),
+            &[parse_limit("api/POST.201.json=1").unwrap()],
+            false,
+        ));
+
+        let requests: Vec<_> = (0..20)
+            .map(|_| {
+                let mocks = mocks.clone();
+                tokio::spawn(async move { status(&mocks, Method::POST, "/api").await })
+            })
+            .collect();
+        let mut created = 0;
+        for request in requests {
+            if request.await.unwrap() == Some(201) {
+                created += 1;
+            }
+        }
+        assert_eq!(created, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn names_the_profiles_of_its_files() {
+        let dir = mock_dir(
+            "profiles",
+            &[
+                ("api/GET.json", "{}"),
+                (
+                    "api/.profiles",
+                    "*=cors\nGET.json=no-store, team\nPOST.json=security\n",
+                ),
+            ],
+        );
+
+        assert_eq!(
+            named_profiles(&dir.join("api/GET.json")).await,
+            ["cors", "no-store", "team"]
+        );
+        assert!(named_profiles(&dir.join("GET.json")).await.is_empty());
+        assert_eq!(method_files(&dir.join("api"), None).await.len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use hyper::{Method, Request, Response};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::body::{full, ProxyBody};
+use crate::lifecycle::{LifecycleEvent, LifecycleHooks};
+
+/// Keeps the mock directory in sync with a git repository.
+///
+/// The repository is cloned into the directory if it is not a clone yet, and otherwise
+/// fetched and reset to the remote branch, so local edits are discarded. Mock files are
+/// read on every request, so synced changes apply immediately.
+pub struct GitSync {
+    repo: String,
+    branch: Option<String>,
+    dir: PathBuf,
+    hooks: Option<Arc<LifecycleHooks>>,
+    // commit of the previous sync, held for the whole sync so there is one at a time,
+    // whether from the interval or from the sync path
+    last_commit: Mutex<Option<String>>,
+}
+
+impl GitSync {
+    pub fn new(
+        repo: String,
+        branch: Option<String>,
+        dir: PathBuf,
+        hooks: Option<Arc<LifecycleHooks>>,
+    ) -> Self {
+        Self {
+            repo,
+            branch,
+            dir,
+            hooks,
+            last_commit: Mutex::new(None),
+        }
+    }
+
+    /// Clone or update the directory, returning the checked out commit. A commit other
+    /// than the previous sync's is reported to the lifecycle webhooks.
+    pub async fn sync(&self) -> Result<String, String> {
+        let mut last_commit = self.last_commit.lock().await;
+
+        if self.dir.join(".git").exists() {
+            let branch = self.branch.as_deref().unwrap_or("HEAD");
+            self.git(&["fetch", "--depth", "1", &self.repo, branch])
+                .await?;
+            self.git(&["reset", "--hard", "FETCH_HEAD"]).await?;
+        } else {
+            let dir = self.dir.to_string_lossy();
+            let mut args = vec!["clone", "--depth", "1"];
+            if let Some(branch) = &self.branch {
+                args.extend(["--branch", branch]);
+            }
+            args.extend([self.repo.as_str(), &dir]);
+            run_git(Command::new("git").args(&args)).await?;
+        }
+
+        let commit = self.git(&["rev-parse", "--short", "HEAD"]).await?;
+
+        let previous = last_commit.replace(commit.clone());
+        if let (Some(hooks), Some(previous)) = (&self.hooks, previous) {
+            if previous != commit {
+                hooks
+                    .emit(
+                        LifecycleEvent::MocksSynced,
+                        json!({ "commit": commit, "previous": previous }),
+                    )
+                    .await;
+            }
+        }
+
+        Ok(commit)
+    }
+
+    /// Sync every `interval` in the background, logging failures
+    pub fn spawn(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // the first tick completes immediately, and startup already synced
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                match self.sync().await {
+                    Ok(commit) => log::debug!("Synced mocks from {} at {commit}", self.repo),
+                    Err(error) => log::warn!("Failed to sync mocks from {}: {error}", self.repo),
+                }
+            }
+        });
+    }
+
+    /// Sync now on POST, answering with the checked out commit
+    pub async fn handle_request<B>(&self, request: &Request<B>) -> Response<ProxyBody> {
+        if request.method() != Method::POST {
+            return Response::builder()
+                .status(405)
+                .header(hyper::header::ALLOW, "POST")
+                .body(full("Method Not Allowed"))
+                .unwrap();
+        }
+
+        let (status, body) = match self.sync().await {
+            Ok(commit) => {
+                log::info!("Synced mocks from {} at {commit}", self.repo);
+                (200, json!({ "commit": commit }))
+            }
+            Err(error) => {
+                log::warn!("Failed to sync mocks from {}: {error}", self.repo);
+                (502, json!({ "error": error }))
+            }
+        };
+
+        Response::builder()
+            .status(status)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(full(body.to_string()))
+            .unwrap()
+    }
+
+    async fn git(&self, args: &[&str]) -> Result<String, String> {
+        run_git(Command::new("git").arg("-C").arg(&self.dir).args(args)).await
+    }
+}
+
+/// Trimmed standard output of a git command, or its standard error if it failed
+async fn run_git(command: &mut Command) -> Result<String, String> {
+    let output = command
+        .output()
+        .await
+        .map_err(|error| format!("failed to run git: {error}"))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
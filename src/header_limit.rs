@@ -0,0 +1,120 @@
+use hyper::server::conn::http1;
+use hyper::Request;
+
+/// Number of headers the HTTP parser accepts by default
+const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// Size of the request head the HTTP parser accepts by default
+const DEFAULT_MAX_HEADER_SIZE: usize = 8192 + 4096 * 100;
+
+/// Limits on the request headers, answered with a 431 that says which limit was hit.
+///
+/// The HTTP parser gets twice the room, so that requests over a limit still reach the
+/// proxy; requests over the parser's own limits get its bare 431 without a body.
+pub struct HeaderLimits {
+    max_headers: Option<usize>,
+    max_header_size: Option<usize>,
+}
+
+impl HeaderLimits {
+    pub fn new(max_headers: Option<usize>, max_header_size: Option<u64>) -> Self {
+        Self {
+            max_headers,
+            max_header_size: max_header_size.map(|size| size as usize),
+        }
+    }
+
+    fn parser_max_headers(&self) -> usize {
+        self.max_headers.map_or(DEFAULT_MAX_HEADERS, |max_headers| {
+            max_headers.saturating_mul(2).max(DEFAULT_MAX_HEADERS)
+        })
+    }
+
+    fn parser_max_header_size(&self) -> usize {
+        self.max_header_size
+            .map_or(DEFAULT_MAX_HEADER_SIZE, |max_header_size| {
+                max_header_size
+                    .saturating_mul(2)
+                    .max(DEFAULT_MAX_HEADER_SIZE)
+            })
+    }
+
+    /// Raise the parser limits above the configured ones
+    pub fn configure(&self, builder: &mut http1::Builder) {
+        builder.max_headers(self.parser_max_headers());
+        builder.max_buf_size(self.parser_max_header_size());
+    }
+
+    /// The limits the parser itself enforces
+    pub fn parser_limits(&self) -> String {
+        format!(
+            "the limit of {} headers or {} bytes",
+            self.parser_max_headers(),
+            self.parser_max_header_size()
+        )
+    }
+
+    /// The limit the request exceeds, if any
+    pub fn check<B>(&self, request: &Request<B>) -> Option<String> {
+        let headers = request.headers().len();
+        if let Some(max_headers) = self.max_headers.filter(|max| headers > *max) {
+            return Some(format!(
+                "Request has {headers} headers, the limit is {max_headers}"
+            ));
+        }
+
+        let size = head_size(request);
+        if let Some(max_header_size) = self.max_header_size.filter(|max| size > *max) {
+            return Some(format!(
+                "Request head is {size} bytes, the limit is {max_header_size} bytes"
+            ));
+        }
+
+        None
+    }
+}
+
+/// Size of the request line and headers as sent over HTTP/1.1
+fn head_size<B>(request: &Request<B>) -> usize {
+    let request_line = request.method().as_str().len() + request.uri().to_string().len() + 12;
+    let headers: usize = request
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum();
+
+    request_line + headers + 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: usize, value: &str) -> Request<()> {
+        let mut builder = Request::get("/api");
+        for index in 0..headers {
+            builder = builder.header(format!("x-header-{index}"), value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn names_the_limit_that_was_hit() {
+        let limits = HeaderLimits::new(Some(4), Some(8192));
+
+        assert_eq!(limits.check(&request(4, "a")), None);
+        assert_eq!(
+            limits.check(&request(5, "a")),
+            Some("Request has 5 headers, the limit is 4".to_owned())
+        );
+        assert!(limits
+            .check(&request(1, &"a".repeat(9000)))
+            .is_some_and(|limit| limit.ends_with("the limit is 8192 bytes")));
+    }
+
+    #[test]
+    fn counts_the_head_as_sent() {
+        // GET /api HTTP/1.1\r\nx-header-0: a\r\n\r\n
+        assert_eq!(head_size(&request(1, "a")), 19 + 15 + 2);
+    }
+}
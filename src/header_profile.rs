@@ -0,0 +1,208 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+
+/// Profiles available without defining them
+const BUILTIN_PROFILES: [(&str, &[(&str, &str)]); 4] = [
+    (
+        "security",
+        &[
+            (
+                "strict-transport-security",
+                "max-age=63072000; includeSubDomains",
+            ),
+            ("x-content-type-options", "nosniff"),
+            ("x-frame-options", "DENY"),
+            ("referrer-policy", "no-referrer"),
+            ("content-security-policy", "default-src 'self'"),
+        ],
+    ),
+    ("cors", &[("access-control-allow-origin", "*")]),
+    ("no-store", &[("cache-control", "no-store")]),
+    (
+        "immutable",
+        &[("cache-control", "public, max-age=31536000, immutable")],
+    ),
+];
+
+/// Profiles a mock file names for itself, carried on its response
+#[derive(Clone)]
+pub struct NamedProfiles(pub Vec<String>);
+
+/// Named sets of response headers, added to mock and static file responses by path prefix
+/// or by the mock file's own choice.
+///
+/// Every route whose prefix matches adds its profiles, longer prefixes last, so their
+/// headers replace those of shorter ones, and the profiles a mock file names come last.
+/// Headers the response already has are kept. Defined headers are added to a built-in
+/// profile of the same name.
+pub struct HeaderProfiles {
+    /// Header sets by name
+    profiles: HashMap<String, HeaderMap>,
+    /// Header sets by path prefix, shortest prefix first
+    routes: Vec<(String, HeaderMap)>,
+}
+
+impl HeaderProfiles {
+    pub fn new(
+        profiles: &[(String, String, String)],
+        routes: &[(String, Vec<String>)],
+    ) -> Result<Self, String> {
+        let mut headers: HashMap<String, HeaderMap> = BUILTIN_PROFILES
+            .iter()
+            .map(|(name, builtin)| {
+                let headers = builtin
+                    .iter()
+                    .map(|(header, value)| {
+                        (
+                            HeaderName::from_static(header),
+                            HeaderValue::from_static(value),
+                        )
+                    })
+                    .collect();
+                (name.to_string(), headers)
+            })
+            .collect();
+
+        for (name, header, value) in profiles {
+            let header = HeaderName::try_from(header)
+                .map_err(|error| format!("invalid header name {header}: {error}"))?;
+            let value = HeaderValue::try_from(value)
+                .map_err(|error| format!("invalid value for {header}: {error}"))?;
+            headers
+                .entry(name.to_owned())
+                .or_default()
+                .insert(header, value);
+        }
+
+        let mut routes = routes
+            .iter()
+            .map(|(prefix, names)| {
+                let mut route_headers = HeaderMap::new();
+                for name in names {
+                    let profile = headers
+                        .get(name.as_str())
+                        .ok_or_else(|| format!("unknown header profile {name}"))?;
+                    route_headers.extend(profile.clone());
+                }
+                Ok((prefix.to_owned(), route_headers))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        routes.sort_by_key(|(prefix, _)| prefix.len());
+
+        Ok(Self {
+            profiles: headers,
+            routes,
+        })
+    }
+
+    /// Add the headers of the routes matching the path and of the named profiles, unless
+    /// the response already has them
+    pub fn apply(&self, path: &str, named: &[String], headers: &mut HeaderMap) {
+        let mut profile_headers = HeaderMap::new();
+        for (_, route_headers) in self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        {
+            profile_headers.extend(route_headers.clone());
+        }
+        for name in named {
+            match self.profiles.get(name) {
+                Some(profile) => profile_headers.extend(profile.clone()),
+                None => log::warn!("Unknown header profile {name} named by the mock for {path}"),
+            }
+        }
+
+        for name in profile_headers.keys() {
+            if !headers.contains_key(name) {
+                for value in profile_headers.get_all(name) {
+                    headers.append(name, value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Parse a `NAME=HEADER:VALUE` profile header
+pub fn parse_header_profile(value: &str) -> Result<(String, String, String), String> {
+    let Some((name, (header, header_value))) = value
+        .split_once('=')
+        .and_then(|(name, header)| Some((name, header.split_once(':')?)))
+    else {
+        return Err(format!("header profile must be NAME=HEADER:VALUE: {value}"));
+    };
+
+    Ok((
+        name.trim().to_owned(),
+        header.trim().to_owned(),
+        header_value.trim().to_owned(),
+    ))
+}
+
+/// Parse a `PREFIX=NAME[,NAME...]` header route
+pub fn parse_header_route(value: &str) -> Result<(String, Vec<String>), String> {
+    let Some((prefix, names)) = value.split_once('=') else {
+        return Err(format!(
+            "header route must be PREFIX=PROFILE[,PROFILE...]: {value}"
+        ));
+    };
+
+    Ok((
+        prefix.to_owned(),
+        names
+            .split(',')
+            .map(|name| name.trim().to_owned())
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles() -> HeaderProfiles {
+        HeaderProfiles::new(
+            &[parse_header_profile("team=X-Team: payments").unwrap()],
+            &[
+                parse_header_route("/=security,no-store").unwrap(),
+                parse_header_route("/assets=immutable").unwrap(),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn keeps_headers_the_response_has() {
+        let mut headers = HeaderMap::new();
+        headers.insert("cache-control", HeaderValue::from_static("max-age=60"));
+
+        profiles().apply("/api", &[], &mut headers);
+
+        assert_eq!(headers["cache-control"], "max-age=60");
+        assert_eq!(headers["x-frame-options"], "DENY");
+    }
+
+    #[test]
+    fn longer_prefixes_and_named_profiles_come_last() {
+        let mut headers = HeaderMap::new();
+        profiles().apply("/assets/app.js", &[], &mut headers);
+        assert_eq!(
+            headers["cache-control"],
+            "public, max-age=31536000, immutable"
+        );
+
+        let mut headers = HeaderMap::new();
+        profiles().apply(
+            "/assets/app.js",
+            &["no-store".to_owned(), "team".to_owned()],
+            &mut headers,
+        );
+        assert_eq!(headers["cache-control"], "no-store");
+        assert_eq!(headers["x-team"], "payments");
+    }
+
+    #[test]
+    fn rejects_unknown_route_profiles() {
+        assert!(HeaderProfiles::new(&[], &[parse_header_route("/=nope").unwrap()]).is_err());
+    }
+}
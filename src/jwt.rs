@@ -0,0 +1,327 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::HeaderMap;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::clock;
+
+/// Shortest time between JWKS reloads for unknown key ids, so tokens with made-up key ids
+/// cannot make the proxy fetch the JWKS on every request
+const MIN_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Validates bearer JWTs against a JWKS document.
+///
+/// The JWKS is loaded at startup from a URL or a file, and reloaded when a token refers to an
+/// unknown key id (at most every 10 seconds), so it may point at the proxy's own mock
+/// issuer. Tokens must carry an expiry. A failed reload keeps the keys loaded before.
+pub struct JwtValidator {
+    jwks_source: String,
+    issuer: Option<String>,
+    audience: Option<String>,
+    paths: Vec<String>,
+    keys: RwLock<Vec<Jwk>>,
+    last_reload: Mutex<Option<Instant>>,
+}
+
+struct Jwk {
+    kid: Option<String>,
+    key: PublicKey,
+}
+
+enum PublicKey {
+    Ec { point: Vec<u8> },
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+}
+
+#[derive(Debug)]
+pub enum JwtError {
+    Missing,
+    Malformed,
+    UnsupportedAlgorithm(String),
+    UnknownKey,
+    InvalidSignature,
+    MissingExpiry,
+    Expired,
+    NotYetValid,
+    WrongIssuer,
+    WrongAudience,
+    JwksUnavailable(String),
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtError::Missing => write!(f, "bearer token is missing"),
+            JwtError::Malformed => write!(f, "token is malformed"),
+            JwtError::UnsupportedAlgorithm(alg) => write!(f, "unsupported algorithm: {alg}"),
+            JwtError::UnknownKey => write!(f, "no matching key in JWKS"),
+            JwtError::InvalidSignature => write!(f, "signature is invalid"),
+            JwtError::MissingExpiry => write!(f, "token has no expiry (exp)"),
+            JwtError::Expired => write!(f, "token is expired"),
+            JwtError::NotYetValid => write!(f, "token is not valid yet"),
+            JwtError::WrongIssuer => write!(f, "issuer does not match"),
+            JwtError::WrongAudience => write!(f, "audience does not match"),
+            JwtError::JwksUnavailable(reason) => write!(f, "failed to load JWKS: {reason}"),
+        }
+    }
+}
+
+impl JwtValidator {
+    pub fn new(
+        jwks_source: String,
+        issuer: Option<String>,
+        audience: Option<String>,
+        paths: Vec<String>,
+    ) -> Self {
+        Self {
+            jwks_source,
+            issuer,
+            audience,
+            paths,
+            keys: RwLock::new(Vec::new()),
+            last_reload: Mutex::new(None),
+        }
+    }
+
+    /// Whether requests to the path must carry a valid token.
+    /// Without configured path prefixes, every path is protected.
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.paths.is_empty() || self.paths.iter().any(|prefix| path.starts_with(prefix))
+    }
+
+    /// Validate the bearer token in the `Authorization` header, and return its claims.
+    pub async fn validate(&self, headers: &HeaderMap) -> Result<Map<String, Value>, JwtError> {
+        let token = headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(JwtError::Missing)?
+            .trim();
+
+        let (signed, signature) = token.rsplit_once('.').ok_or(JwtError::Malformed)?;
+        let (header, claims) = signed.split_once('.').ok_or(JwtError::Malformed)?;
+
+        let header = decode_json(header)?;
+        let claims = decode_json(claims)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| JwtError::Malformed)?;
+
+        let alg = header.get("alg").and_then(Value::as_str).unwrap_or("");
+        let kid = header.get("kid").and_then(Value::as_str);
+
+        if !self
+            .verify_with_known_keys(alg, kid, signed, &signature)
+            .await?
+        {
+            // the key may have been rotated, or the JWKS was not reachable before
+            self.refresh_keys().await?;
+            if !self
+                .verify_with_known_keys(alg, kid, signed, &signature)
+                .await?
+            {
+                return Err(JwtError::UnknownKey);
+            }
+        }
+
+        self.check_claims(&claims)?;
+
+        Ok(claims)
+    }
+
+    /// Ok(false) if no key matched the token's key id
+    async fn verify_with_known_keys(
+        &self,
+        alg: &str,
+        kid: Option<&str>,
+        signed: &str,
+        signature: &[u8],
+    ) -> Result<bool, JwtError> {
+        let keys = self.keys.read().await;
+
+        let mut candidates = keys
+            .iter()
+            .filter(|jwk| kid.is_none() || jwk.kid.as_deref() == kid)
+            .peekable();
+
+        if candidates.peek().is_none() {
+            return Ok(false);
+        }
+
+        for jwk in candidates {
+            if verify_signature(alg, &jwk.key, signed.as_bytes(), signature)? {
+                return Ok(true);
+            }
+        }
+
+        Err(JwtError::InvalidSignature)
+    }
+
+    /// Load the JWKS ahead of the first request, returning the number of keys
+    pub async fn preload(&self) -> Result<usize, JwtError> {
+        self.reload_keys().await?;
+        Ok(self.keys.read().await.len())
+    }
+
+    /// Reload the JWKS, unless it was reloaded within the minimum interval. Concurrent
+    /// callers wait for the reload in progress instead of starting their own.
+    async fn refresh_keys(&self) -> Result<(), JwtError> {
+        let mut last_reload = self.last_reload.lock().await;
+        if last_reload.is_some_and(|at| at.elapsed() < MIN_RELOAD_INTERVAL) {
+            return Ok(());
+        }
+
+        // failed reloads count too, so an unreachable JWKS is not retried on every request
+        *last_reload = Some(Instant::now());
+        self.reload_keys().await
+    }
+
+    async fn reload_keys(&self) -> Result<(), JwtError> {
+        let document = if self.jwks_source.starts_with("http://")
+            || self.jwks_source.starts_with("https://")
+        {
+            let response = reqwest::get(&self.jwks_source)
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|error| JwtError::JwksUnavailable(error.to_string()))?;
+            response
+                .bytes()
+                .await
+                .map_err(|error| JwtError::JwksUnavailable(error.to_string()))?
+                .to_vec()
+        } else {
+            tokio::fs::read(&self.jwks_source)
+                .await
+                .map_err(|error| JwtError::JwksUnavailable(error.to_string()))?
+        };
+
+        let document: Value = serde_json::from_slice(&document)
+            .map_err(|error| JwtError::JwksUnavailable(error.to_string()))?;
+
+        // a document without keys keeps the current ones
+        let keys = document
+            .get("keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| JwtError::JwksUnavailable("document has no keys array".to_owned()))?
+            .iter()
+            .filter_map(parse_jwk)
+            .collect();
+
+        *self.keys.write().await = keys;
+
+        Ok(())
+    }
+
+    fn check_claims(&self, claims: &Map<String, Value>) -> Result<(), JwtError> {
+        let now = clock::unix_now();
+
+        let expires_at = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or(JwtError::MissingExpiry)?;
+        if now >= expires_at {
+            return Err(JwtError::Expired);
+        }
+
+        if let Some(not_before) = claims.get("nbf").and_then(Value::as_u64) {
+            if now < not_before {
+                return Err(JwtError::NotYetValid);
+            }
+        }
+
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+                return Err(JwtError::WrongIssuer);
+            }
+        }
+
+        if let Some(audience) = &self.audience {
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !matches {
+                return Err(JwtError::WrongAudience);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_json(part: &str) -> Result<Map<String, Value>, JwtError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| JwtError::Malformed)?;
+
+    match serde_json::from_slice(&bytes) {
+        Ok(Value::Object(map)) => Ok(map),
+        _ => Err(JwtError::Malformed),
+    }
+}
+
+fn parse_jwk(key: &Value) -> Option<Jwk> {
+    let field = |name: &str| {
+        key.get(name)
+            .and_then(Value::as_str)
+            .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+    };
+
+    let public_key = match key.get("kty").and_then(Value::as_str)? {
+        "EC" => {
+            let mut point = vec![0x04];
+            point.extend(field("x")?);
+            point.extend(field("y")?);
+            PublicKey::Ec { point }
+        }
+        "RSA" => PublicKey::Rsa {
+            n: field("n")?,
+            e: field("e")?,
+        },
+        _ => return None,
+    };
+
+    Some(Jwk {
+        kid: key.get("kid").and_then(Value::as_str).map(str::to_owned),
+        key: public_key,
+    })
+}
+
+fn verify_signature(
+    alg: &str,
+    key: &PublicKey,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, JwtError> {
+    let verified = match (alg, key) {
+        ("ES256", PublicKey::Ec { point }) => {
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(message, signature)
+                .is_ok()
+        }
+        ("ES384", PublicKey::Ec { point }) => {
+            UnparsedPublicKey::new(&signature::ECDSA_P384_SHA384_FIXED, point)
+                .verify(message, signature)
+                .is_ok()
+        }
+        ("RS256" | "RS384" | "RS512", PublicKey::Rsa { n, e }) => {
+            let parameters = match alg {
+                "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+            };
+            RsaPublicKeyComponents { n, e }
+                .verify(parameters, message, signature)
+                .is_ok()
+        }
+        ("ES256" | "ES384" | "RS256" | "RS384" | "RS512", _) => false,
+        _ => return Err(JwtError::UnsupportedAlgorithm(alg.to_owned())),
+    };
+
+    Ok(verified)
+}
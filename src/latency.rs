@@ -0,0 +1,72 @@
+use http_body_util::BodyExt;
+use hyper::{HeaderMap, Response};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::body::ProxyBody;
+use crate::constants::LATENCY_PROFILE_HEADER;
+use crate::throttle::{default_burst, Throttled};
+
+/// Simulated network conditions between the client and the proxy.
+///
+/// Responses are held back for one round trip, and their bodies are released at the
+/// profile's bandwidth.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LatencyProfile {
+    /// No simulated latency
+    Off,
+    /// 1ms round trip, unlimited bandwidth
+    SameAz,
+    /// 150ms round trip, 100Mbit/s
+    CrossRegion,
+    /// 70ms round trip, 12Mbit/s
+    #[value(name = "mobile-4g")]
+    #[serde(rename = "mobile-4g")]
+    Mobile4g,
+    /// 300ms round trip, 750kbit/s
+    #[value(name = "mobile-3g")]
+    #[serde(rename = "mobile-3g")]
+    Mobile3g,
+}
+
+impl LatencyProfile {
+    /// Profile chosen by the `X-Latency-Profile` request header, if any
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        use clap::ValueEnum;
+
+        let name = headers.get(LATENCY_PROFILE_HEADER)?.to_str().ok()?;
+        Self::from_str(name.trim(), true).ok()
+    }
+
+    pub fn round_trip(self) -> Duration {
+        match self {
+            Self::Off => Duration::ZERO,
+            Self::SameAz => Duration::from_millis(1),
+            Self::CrossRegion => Duration::from_millis(150),
+            Self::Mobile4g => Duration::from_millis(70),
+            Self::Mobile3g => Duration::from_millis(300),
+        }
+    }
+
+    /// Bandwidth in bytes per second, `None` for unlimited
+    pub fn bandwidth(self) -> Option<u64> {
+        match self {
+            Self::Off | Self::SameAz => None,
+            Self::CrossRegion => Some(100_000_000 / 8),
+            Self::Mobile4g => Some(12_000_000 / 8),
+            Self::Mobile3g => Some(750_000 / 8),
+        }
+    }
+
+    /// Delay the response by a round trip, and throttle its body
+    pub async fn apply(self, response: Response<ProxyBody>) -> Response<ProxyBody> {
+        tokio::time::sleep(self.round_trip()).await;
+
+        match self.bandwidth() {
+            Some(bandwidth) => response
+                .map(|body| Throttled::new(body, bandwidth, default_burst(bandwidth)).boxed()),
+            None => response,
+        }
+    }
+}
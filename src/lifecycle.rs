@@ -0,0 +1,76 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::clock;
+
+/// Time allowed for each webhook delivery
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LifecycleEvent {
+    /// Startup loading finished and traffic is accepted
+    Started,
+    /// Same as `Started`, for a server that took over the sockets of a previous one
+    UpgradeCompleted,
+    /// Startup loading failed, so traffic keeps being refused
+    LoadFailed,
+    /// The mock repository checked out a new commit
+    MocksSynced,
+    /// An exit signal was received
+    Stopping,
+}
+
+/// Posts lifecycle events as JSON to webhook URLs, so orchestration tooling can react
+/// without polling.
+pub struct LifecycleHooks {
+    client: reqwest::Client,
+    urls: Vec<String>,
+}
+
+impl LifecycleHooks {
+    pub fn new(urls: Vec<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            urls,
+        }
+    }
+
+    /// Post the event to every webhook at once, and wait for the deliveries. Failures are
+    /// logged, not retried.
+    pub async fn emit(&self, event: LifecycleEvent, detail: Value) {
+        let payload = json!({
+            "event": event,
+            "time": clock::rfc3339(clock::now()),
+            "pid": std::process::id(),
+            "detail": detail,
+        });
+
+        let payload = payload.to_string();
+        let mut deliveries = tokio::task::JoinSet::new();
+        for url in &self.urls {
+            let request = self
+                .client
+                .post(url)
+                .timeout(DELIVERY_TIMEOUT)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(payload.clone());
+            let url = url.clone();
+
+            deliveries.spawn(async move {
+                match request
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                {
+                    Ok(_) => log::debug!("Delivered lifecycle event {event:?} to {url}"),
+                    Err(error) => {
+                        log::warn!("Failed to deliver lifecycle event {event:?} to {url}: {error}")
+                    }
+                }
+            });
+        }
+        while deliveries.join_next().await.is_some() {}
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps the number of concurrent requests sent to each upstream.
+///
+/// Requests beyond `max_in_flight` wait in a small per-upstream queue; once the queue is full,
+/// further requests are rejected. Upstreams are tracked only while they have requests in
+/// flight or waiting, as the `Proxy-Host` values come from clients.
+pub struct UpstreamLimiter {
+    max_in_flight: usize,
+    queue_size: usize,
+    upstreams: std::sync::Mutex<HashMap<String, Arc<UpstreamSlot>>>,
+}
+
+struct UpstreamSlot {
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub struct LimitExceeded;
+
+impl UpstreamLimiter {
+    pub fn new(max_in_flight: NonZeroUsize, queue_size: usize) -> Self {
+        Self {
+            max_in_flight: max_in_flight.get(),
+            queue_size,
+            upstreams: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn slot(&self, upstream: &str) -> Arc<UpstreamSlot> {
+        let mut upstreams = self.upstreams.lock().unwrap();
+
+        if let Some(slot) = upstreams.get(upstream) {
+            return slot.clone();
+        }
+
+        // forget idle upstreams; a slot or semaphore still referenced has requests waiting or
+        // in flight, and new references are only taken under this lock
+        upstreams.retain(|_, slot| {
+            Arc::strong_count(slot) > 1 || Arc::strong_count(&slot.semaphore) > 1
+        });
+
+        let slot = Arc::new(UpstreamSlot {
+            semaphore: Arc::new(Semaphore::new(self.max_in_flight)),
+            waiting: AtomicUsize::new(0),
+        });
+        upstreams.insert(upstream.to_owned(), slot.clone());
+
+        slot
+    }
+
+    /// Acquire an in-flight slot for the upstream, waiting in the queue if necessary.
+    /// The slot is released when the returned permit is dropped.
+    pub async fn acquire(&self, upstream: &str) -> Result<OwnedSemaphorePermit, LimitExceeded> {
+        let slot = self.slot(upstream);
+
+        if let Ok(permit) = slot.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        // join the queue unless it is already full
+        let queued = slot
+            .waiting
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |waiting| {
+                (waiting < self.queue_size).then_some(waiting + 1)
+            });
+
+        if queued.is_err() {
+            return Err(LimitExceeded);
+        }
+
+        // leave the queue even if the client goes away while waiting
+        let _queued = QueueGuard(&slot.waiting);
+
+        slot.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| LimitExceeded)
+    }
+
+    /// Take a free in-flight slot for the upstream without queueing, as for a hedged
+    /// duplicate that is only worth sending when the upstream has room
+    pub fn try_acquire(&self, upstream: &str) -> Option<OwnedSemaphorePermit> {
+        self.slot(upstream)
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .ok()
+    }
+}
+
+struct QueueGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(max_in_flight: usize, queue_size: usize) -> Arc<UpstreamLimiter> {
+        Arc::new(UpstreamLimiter::new(
+            NonZeroUsize::new(max_in_flight).unwrap(),
+            queue_size,
+        ))
+    }
+
+    #[tokio::test]
+    async fn limits_each_upstream_separately() {
+        let limiter = limiter(1, 0);
+        let _a = limiter.acquire("http://a").await.unwrap();
+        assert!(limiter.acquire("http://a").await.is_err());
+        assert!(limiter.acquire("http://b").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn queues_until_a_slot_is_released() {
+        let limiter = limiter(1, 1);
+        let first = limiter.acquire("http://a").await.unwrap();
+
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("http://a").await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // the queue holds one request, so the next one is rejected
+        assert!(limiter.acquire("http://a").await.is_err());
+
+        drop(first);
+        assert!(queued.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn forgets_idle_upstreams() {
+        let limiter = limiter(1, 0);
+        let busy = limiter.acquire("http://busy").await.unwrap();
+        for index in 0..100 {
+            drop(
+                limiter
+                    .acquire(&format!("http://idle-{index}"))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let upstreams = limiter.upstreams.lock().unwrap();
+        assert!(upstreams.len() <= 2);
+        assert!(upstreams.contains_key("http://busy"));
+        drop(busy);
+    }
+
+    #[tokio::test]
+    async fn takes_a_free_slot_without_queueing() {
+        let limiter = limiter(1, 1);
+        let permit = limiter.try_acquire("http://a").unwrap();
+        assert!(limiter.try_acquire("http://a").is_none());
+        drop(permit);
+        assert!(limiter.try_acquire("http://a").is_some());
+    }
+}
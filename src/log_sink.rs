@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::time::SystemTime;
+
+use crate::clock;
+
+const APP_NAME: &str = "affogato";
+const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// syslog facility "user-level messages"
+const FACILITY_USER: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogSinkKind {
+    /// RFC 5424 messages to a local socket or a `udp://host:port` collector
+    Syslog,
+    /// systemd-journald native protocol
+    Journald,
+}
+
+enum Transport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+/// Sends log messages to syslog or journald, one datagram per message.
+pub struct LogSink {
+    kind: LogSinkKind,
+    transport: Transport,
+    hostname: String,
+    pid: u32,
+}
+
+impl LogSink {
+    /// Connect to the sink. `syslog_address` is a socket path or `udp://host:port`
+    /// (default /dev/log), and is ignored for journald.
+    pub fn connect(kind: LogSinkKind, syslog_address: Option<&str>) -> io::Result<Self> {
+        let transport = match kind {
+            LogSinkKind::Syslog => {
+                match syslog_address
+                    .unwrap_or(DEFAULT_SYSLOG_SOCKET)
+                    .strip_prefix("udp://")
+                {
+                    Some(address) => {
+                        let socket = UdpSocket::bind(if address.starts_with('[') {
+                            "[::]:0"
+                        } else {
+                            "0.0.0.0:0"
+                        })?;
+                        socket.connect(address)?;
+                        Transport::Udp(socket)
+                    }
+                    None => {
+                        let socket = UnixDatagram::unbound()?;
+                        socket.connect(syslog_address.unwrap_or(DEFAULT_SYSLOG_SOCKET))?;
+                        Transport::Unix(socket)
+                    }
+                }
+            }
+            LogSinkKind::Journald => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(JOURNALD_SOCKET)?;
+                Transport::Unix(socket)
+            }
+        };
+
+        let mut hostname = [0u8; 256];
+        let hostname = nix::unistd::gethostname(&mut hostname)
+            .ok()
+            .and_then(|hostname| hostname.to_str().ok())
+            .filter(|hostname| !hostname.is_empty())
+            .unwrap_or("-")
+            .to_owned();
+
+        Ok(Self {
+            kind,
+            transport,
+            hostname,
+            pid: std::process::id(),
+        })
+    }
+
+    /// Send one message. `target` names the source, e.g. the module or "access".
+    pub fn send(&self, level: log::Level, target: &str, message: &str) -> io::Result<()> {
+        let datagram = match self.kind {
+            LogSinkKind::Syslog => self.syslog_message(level, target, message),
+            LogSinkKind::Journald => journald_message(level, target, message),
+        };
+
+        match &self.transport {
+            Transport::Unix(socket) => socket.send(&datagram)?,
+            Transport::Udp(socket) => socket.send(&datagram)?,
+        };
+
+        Ok(())
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID - MSG`
+    fn syslog_message(&self, level: log::Level, target: &str, message: &str) -> Vec<u8> {
+        let priority = FACILITY_USER * 8 + severity(level);
+
+        // MSGID is at most 32 printable characters without spaces
+        let msgid: String = target
+            .chars()
+            .filter(|c| c.is_ascii_graphic())
+            .take(32)
+            .collect();
+        let msgid = if msgid.is_empty() { "-" } else { &msgid };
+
+        format!(
+            "<{priority}>1 {} {} {APP_NAME} {} {msgid} - {message}",
+            clock::rfc3339(SystemTime::now()),
+            self.hostname,
+            self.pid,
+        )
+        .into_bytes()
+    }
+}
+
+/// Native journald fields. Values are length-prefixed so they may contain newlines.
+fn journald_message(level: log::Level, target: &str, message: &str) -> Vec<u8> {
+    let mut datagram = Vec::new();
+
+    for (field, value) in [
+        ("MESSAGE", message),
+        ("PRIORITY", &severity(level).to_string()),
+        ("SYSLOG_IDENTIFIER", APP_NAME),
+        ("LOG_TARGET", target),
+    ] {
+        datagram.extend_from_slice(field.as_bytes());
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+
+    datagram
+}
+
+/// syslog severity of a log level
+fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// Application logger that filters like env_logger, and writes to a sink instead
+pub struct SinkLogger {
+    filter: env_logger::Logger,
+    sink: LogSink,
+}
+
+impl SinkLogger {
+    pub fn init(filter: env_logger::Logger, sink: LogSink) -> Result<(), log::SetLoggerError> {
+        let max_level = filter.filter();
+        log::set_boxed_logger(Box::new(Self { filter, sink }))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl log::Log for SinkLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.filter.matches(record) {
+            let _ = self
+                .sink
+                .send(record.level(), record.target(), &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -1,8 +1,47 @@
+mod access_log;
+mod auth;
+mod bench;
+mod body;
+mod capture;
 mod cli;
+mod client;
+mod clock;
+mod compression;
 pub mod constants;
+mod context;
+mod csrf;
+mod deadline;
+mod dns;
+mod error_page;
+mod fs_mock;
+mod git_sync;
+mod header_limit;
+mod header_profile;
+mod jwt;
+mod latency;
+mod lifecycle;
+mod limiter;
+mod log_sink;
+mod mirror;
+mod oidc;
 mod proxy;
+mod readiness;
+mod redact;
+mod replay;
+mod reset;
+mod rotation;
+mod secret;
 mod shutdown;
+mod signature;
+mod sigv4;
+mod smtp;
+mod snapshot;
 mod socket;
+mod static_files;
+mod stats;
+mod throttle;
+mod timing;
+mod utility;
 
 use constants::UPGRADE_SOCKET_PATH;
 use hyper::server::conn::http1;
@@ -21,11 +60,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info")
     }
-    env_logger::init();
-
     let command = cli::parse_command();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(path) = &command.value.log_file {
+        let rotation = rotation::Rotation::new(
+            command.value.log_max_size,
+            command.value.log_rotate_interval,
+            command.value.log_keep,
+        );
+        let file = rotation::RotatingFile::open(path, rotation).expect("Failed to open log file");
+        logger.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+    match command.value.log_sink {
+        Some(kind) => {
+            let sink = log_sink::LogSink::connect(kind, command.value.syslog_address.as_deref())
+                .expect("Failed to connect log sink");
+            log_sink::SinkLogger::init(logger.build(), sink).expect("Failed to set logger");
+        }
+        None => logger.init(),
+    }
+
     log::debug!("{:?}", command.value);
 
+    if let Some(cli::Action::Bench(flags)) = command.action {
+        return bench::run(flags).await;
+    }
+
+    if let Some(cli::Action::Replay(flags)) = command.action {
+        return replay::run(flags).await;
+    }
+
+    if let Some(cli::Action::Config {
+        action: cli::ConfigAction::Dump,
+    }) = command.action
+    {
+        println!("{}", serde_json::to_string_pretty(&command.value)?);
+        return Ok(());
+    }
+
     // create address from command line arguments
     let port = command.value.port;
     let address = IpAddr::from_str(&command.value.address).unwrap();
@@ -72,6 +145,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         listener
     };
 
+    clock::set_offset(command.value.clock_offset);
+
+    let proxy_state = Arc::new(proxy::ProxyState::new(&command.value));
+
+    if let Some(dns_port) = command.value.dns_port {
+        let dns_server = Arc::new(dns::DnsServer::new(
+            command.value.dns_record.clone(),
+            command.value.dns_failure.clone(),
+            command.value.dns_ttl,
+        ));
+        dns_server
+            .serve(SocketAddr::from((address, dns_port)))
+            .await
+            .expect("Failed to start DNS server");
+    }
+
+    if let Some(smtp_port) = command.value.smtp_port {
+        let smtp_listener = TcpListener::bind(SocketAddr::from((address, smtp_port)))
+            .await
+            .expect("Failed to bind SMTP listener");
+        let smtp_capture = smtp::SmtpCapture::new(command.value.smtp_dir.clone())
+            .expect("Failed to create SMTP capture directory");
+        log::info!("SMTP capture listening on {}", smtp_listener.local_addr()?);
+        Arc::new(smtp_capture).serve(smtp_listener);
+    }
+
+    // the proxy answers requests over the header limits with a 431 that names the limit;
+    // only requests over the parser's own, higher limits get hyper's bare 431
+    let header_limits =
+        header_limit::HeaderLimits::new(command.value.max_headers, command.value.max_header_size);
+    let mut http_builder = http1::Builder::new();
+    header_limits.configure(&mut http_builder);
+    let header_limits = header_limits.parser_limits();
+
+    let exit_state = proxy_state.clone();
+
+    let loading_state = proxy_state.clone();
+    tokio::spawn(async move { loading_state.load().await });
+
     // server thread
     // create TCP listener bound to the address
     tokio::spawn(async move {
@@ -79,20 +191,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
         // main loop
         loop {
-            let Ok((stream, _)) = listener.accept().await else {
+            let Ok((stream, client_addr)) = listener.accept().await else {
                 continue;
             };
 
+            let connection = stats::CONNECTIONS.track();
             let io_stream = TokioIo::new(stream);
+            let proxy_state = proxy_state.clone();
+            let http_builder = http_builder.clone();
+            let header_limits = header_limits.clone();
 
             // Spawn a tokio task to serve multiple connections concurrently
             tokio::task::spawn(async move {
-                if let Err(err) = http1::Builder::new()
-                    .serve_connection(io_stream, service_fn(proxy::handle_proxy_request))
+                if let Err(err) = http_builder
+                    .serve_connection(
+                        io_stream,
+                        service_fn(move |request| {
+                            proxy::handle_proxy_request(request, proxy_state.clone(), client_addr)
+                        }),
+                    )
+                    .with_upgrades()
                     .await
                 {
-                    eprintln!("Error serving connection: {:?}", err);
+                    if err.is_parse_too_large() {
+                        log::warn!("Rejected request with 431: headers exceed {header_limits}");
+                    } else {
+                        eprintln!("Error serving connection: {:?}", err);
+                    }
                 }
+
+                drop(connection);
             });
         }
     });
@@ -100,5 +228,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // waiting for exit signal
     shutdown::handle_shutdown(file_descriptors).await;
 
-    return Ok(());
+    if let Some(lifecycle) = &exit_state.lifecycle {
+        lifecycle
+            .emit(lifecycle::LifecycleEvent::Stopping, serde_json::json!({}))
+            .await;
+    }
+
+    exit_state.flush().await;
+    std::process::exit(exit_state.exit_code());
 }
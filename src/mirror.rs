@@ -0,0 +1,112 @@
+use serde_json::Value;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::capture::{self, Exchange};
+use crate::redact::Redactor;
+
+/// Records waiting to be published; more are dropped
+const QUEUE_SIZE: usize = 1024;
+
+/// Wait before reconnecting to the server
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Publishes every proxied exchange, as a HAR entry in JSON, to a NATS subject.
+///
+/// Records are published by a background task, which reconnects after failures. While the
+/// server is slow or gone, records beyond the queue are dropped, so requests never wait
+/// on the mirror.
+pub struct NatsMirror {
+    max_body_size: Option<usize>,
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl NatsMirror {
+    /// `address` is the server's `host:port`
+    pub fn start(address: String, subject: String, max_body_size: Option<usize>) -> Self {
+        let (sender, mut receiver) = mpsc::channel(QUEUE_SIZE);
+
+        tokio::spawn(async move {
+            loop {
+                if let Err(error) = publish_all(&address, &subject, &mut receiver).await {
+                    log::warn!("NATS mirror to {address}: {error}");
+                }
+                if receiver.is_closed() {
+                    return;
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+
+        Self {
+            max_body_size,
+            sender,
+        }
+    }
+
+    pub fn publish(&self, exchange: &Exchange, redactor: &Redactor) {
+        let record = capture::har_record(exchange, redactor, self.max_body_size).to_string();
+        if self.sender.try_send(record.into_bytes()).is_err() {
+            log::debug!("NATS mirror queue is full, dropped a record");
+        }
+    }
+}
+
+/// Connect, then publish records as they come and answer the server's pings, until the
+/// connection fails
+async fn publish_all(
+    address: &str,
+    subject: &str,
+    receiver: &mut mpsc::Receiver<Vec<u8>>,
+) -> std::io::Result<()> {
+    let stream = TcpStream::connect(address).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // the server greets with INFO {...}, which gives the largest payload it accepts
+    let info = lines.next_line().await?.unwrap_or_default();
+    let max_payload = info
+        .strip_prefix("INFO ")
+        .and_then(|info| serde_json::from_str::<Value>(info).ok())
+        .and_then(|info| info.get("max_payload").and_then(Value::as_u64))
+        .unwrap_or(1024 * 1024) as usize;
+
+    writer
+        .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"affogato\"}\r\n")
+        .await?;
+    log::info!("NATS mirror connected to {address}, publishing to {subject}");
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Err(std::io::Error::other("connection closed by the server"));
+                };
+                if line == "PING" {
+                    writer.write_all(b"PONG\r\n").await?;
+                } else if let Some(error) = line.strip_prefix("-ERR ") {
+                    log::warn!("NATS mirror to {address}: server error {error}");
+                }
+            }
+            record = receiver.recv() => {
+                let Some(record) = record else {
+                    return Ok(());
+                };
+                if record.len() > max_payload {
+                    log::warn!(
+                        "NATS mirror record of {} bytes exceeds the server limit of {max_payload}, dropped",
+                        record.len()
+                    );
+                    continue;
+                }
+
+                let mut message = format!("PUB {subject} {}\r\n", record.len()).into_bytes();
+                message.extend_from_slice(&record);
+                message.extend_from_slice(b"\r\n");
+                writer.write_all(&message).await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use http_body_util::BodyExt;
+use hyper::{Method, Request, Response};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::body::{full, ProxyBody};
+use crate::clock;
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const JWKS_PATH: &str = "/.well-known/jwks.json";
+const AUTHORIZE_PATH: &str = "/oauth/authorize";
+const TOKEN_PATH: &str = "/oauth/token";
+
+const DEFAULT_SUBJECT: &str = "mock-user";
+const DEFAULT_AUDIENCE: &str = "affogato";
+
+/// Mock OpenID Connect identity provider.
+///
+/// Issues ES256-signed JWTs for any client and grant, so applications under test can run
+/// real auth flows (client credentials, password, authorization code) against the proxy.
+/// The signing key is generated at startup and published through the JWKS endpoint.
+pub struct OidcIssuer {
+    issuer: String,
+    claims: Vec<(String, Value)>,
+    token_ttl: u64,
+    key_pair: EcdsaKeyPair,
+    key_id: String,
+    rng: SystemRandom,
+}
+
+impl OidcIssuer {
+    pub fn new(
+        issuer: &str,
+        claims: Vec<(String, Value)>,
+        token_ttl: u64,
+    ) -> Result<Self, ring::error::Unspecified> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)?;
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .map_err(|_| ring::error::Unspecified)?;
+
+        let mut key_id = [0u8; 8];
+        rng.fill(&mut key_id)?;
+
+        Ok(Self {
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            claims,
+            token_ttl,
+            key_pair,
+            key_id: hex::encode(key_id),
+            rng,
+        })
+    }
+
+    /// Whether the path belongs to the issuer's endpoints
+    pub fn handles(&self, path: &str) -> bool {
+        matches!(
+            path,
+            DISCOVERY_PATH | JWKS_PATH | AUTHORIZE_PATH | TOKEN_PATH
+        )
+    }
+
+    pub async fn handle_request(
+        &self,
+        request: Request<hyper::body::Incoming>,
+    ) -> Response<ProxyBody> {
+        let method = request.method().to_owned();
+        let path = request.uri().path().to_owned();
+        let query = parse_form(request.uri().query().unwrap_or("").as_bytes());
+
+        match (method, path.as_str()) {
+            (Method::GET, DISCOVERY_PATH) => json_response(200, &self.discovery_document()),
+            (Method::GET, JWKS_PATH) => json_response(200, &self.jwks()),
+            (Method::GET, AUTHORIZE_PATH) => self.authorize(&query),
+            (Method::POST, TOKEN_PATH) => {
+                let basic_client_id = basic_client_id(request.headers());
+                let Ok(body) = request.into_body().collect().await else {
+                    return oauth_error(400, "invalid_request", "Failed to read request body");
+                };
+                let mut form = parse_form(&body.to_bytes());
+                if let Some(client_id) = basic_client_id {
+                    form.insert("client_id".to_owned(), client_id);
+                }
+                self.token(&form)
+            }
+            _ => Response::builder()
+                .status(405)
+                .body(full("Method not allowed"))
+                .unwrap(),
+        }
+    }
+
+    fn discovery_document(&self) -> Value {
+        json!({
+            "issuer": self.issuer,
+            "authorization_endpoint": format!("{}{AUTHORIZE_PATH}", self.issuer),
+            "token_endpoint": format!("{}{TOKEN_PATH}", self.issuer),
+            "jwks_uri": format!("{}{JWKS_PATH}", self.issuer),
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["ES256"],
+            "grant_types_supported": ["authorization_code", "client_credentials", "password", "refresh_token"],
+            "token_endpoint_auth_methods_supported": ["client_secret_post", "client_secret_basic", "none"],
+        })
+    }
+
+    fn jwks(&self) -> Value {
+        // uncompressed P-256 point: 0x04 || x || y
+        let public_key = self.key_pair.public_key().as_ref();
+        let (x, y) = public_key[1..].split_at(32);
+
+        json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "use": "sig",
+                "alg": "ES256",
+                "kid": self.key_id,
+                "x": URL_SAFE_NO_PAD.encode(x),
+                "y": URL_SAFE_NO_PAD.encode(y),
+            }]
+        })
+    }
+
+    /// Approve every authorization request immediately.
+    /// The code carries the request parameters, so no state is kept between the two steps.
+    fn authorize(&self, query: &HashMap<String, String>) -> Response<ProxyBody> {
+        let Some(redirect_uri) = query.get("redirect_uri") else {
+            return oauth_error(400, "invalid_request", "redirect_uri is missing");
+        };
+
+        let code = json!({
+            "client_id": query.get("client_id"),
+            "scope": query.get("scope"),
+            "nonce": query.get("nonce"),
+            "sub": query.get("login_hint"),
+        });
+        let code = URL_SAFE_NO_PAD.encode(code.to_string());
+
+        let mut location = redirect_uri.to_owned();
+        location.push(if location.contains('?') { '&' } else { '?' });
+        let mut params = form_urlencoded::Serializer::new(String::new());
+        params.append_pair("code", &code);
+        if let Some(state) = query.get("state") {
+            params.append_pair("state", state);
+        }
+        location.push_str(&params.finish());
+
+        Response::builder()
+            .status(302)
+            .header(hyper::header::LOCATION, location)
+            .body(full(""))
+            .unwrap()
+    }
+
+    fn token(&self, form: &HashMap<String, String>) -> Response<ProxyBody> {
+        let mut client_id = form.get("client_id").cloned();
+        let mut scope = form.get("scope").cloned();
+        let mut nonce = None;
+        let mut subject = form.get("username").cloned();
+
+        let grant_type = form.get("grant_type").map(String::as_str);
+
+        if grant_type == Some("refresh_token") {
+            subject = form
+                .get("refresh_token")
+                .and_then(|token| URL_SAFE_NO_PAD.decode(token).ok())
+                .and_then(|token| String::from_utf8(token).ok());
+        }
+
+        if grant_type == Some("authorization_code") {
+            let code = form
+                .get("code")
+                .and_then(|code| URL_SAFE_NO_PAD.decode(code).ok())
+                .and_then(|code| serde_json::from_slice::<Value>(&code).ok());
+
+            let Some(code) = code else {
+                return oauth_error(400, "invalid_grant", "code is missing or malformed");
+            };
+
+            let field = |name: &str| code.get(name).and_then(Value::as_str).map(str::to_owned);
+            client_id = client_id.or_else(|| field("client_id"));
+            scope = scope.or_else(|| field("scope"));
+            nonce = field("nonce");
+            subject = field("sub");
+        }
+
+        let subject = subject
+            .or_else(|| client_id.clone())
+            .unwrap_or_else(|| DEFAULT_SUBJECT.to_owned());
+        let audience = client_id.unwrap_or_else(|| DEFAULT_AUDIENCE.to_owned());
+
+        let issued_at = clock::unix_now();
+
+        let mut claims = Map::new();
+        claims.insert("iss".to_owned(), json!(self.issuer));
+        claims.insert("sub".to_owned(), json!(subject));
+        claims.insert("aud".to_owned(), json!(audience));
+        claims.insert("iat".to_owned(), json!(issued_at));
+        claims.insert("exp".to_owned(), json!(issued_at + self.token_ttl));
+        if let Some(scope) = &scope {
+            claims.insert("scope".to_owned(), json!(scope));
+        }
+        for (key, value) in &self.claims {
+            claims.insert(key.to_owned(), value.clone());
+        }
+
+        let Ok(access_token) = self.sign(&claims) else {
+            return oauth_error(500, "server_error", "Failed to sign token");
+        };
+
+        if let Some(nonce) = nonce {
+            claims.insert("nonce".to_owned(), json!(nonce));
+        }
+        let Ok(id_token) = self.sign(&claims) else {
+            return oauth_error(500, "server_error", "Failed to sign token");
+        };
+
+        let mut response = json!({
+            "access_token": access_token,
+            "id_token": id_token,
+            "refresh_token": URL_SAFE_NO_PAD.encode(subject),
+            "token_type": "Bearer",
+            "expires_in": self.token_ttl,
+        });
+        if let Some(scope) = scope {
+            response["scope"] = json!(scope);
+        }
+
+        json_response(200, &response)
+    }
+
+    fn sign(&self, claims: &Map<String, Value>) -> Result<String, ring::error::Unspecified> {
+        let header = json!({ "alg": "ES256", "typ": "JWT", "kid": self.key_id });
+
+        let mut token = URL_SAFE_NO_PAD.encode(header.to_string());
+        token.push('.');
+        token.push_str(&URL_SAFE_NO_PAD.encode(Value::Object(claims.clone()).to_string()));
+
+        let signature = self.key_pair.sign(&self.rng, token.as_bytes())?;
+        token.push('.');
+        token.push_str(&URL_SAFE_NO_PAD.encode(signature.as_ref()));
+
+        Ok(token)
+    }
+}
+
+/// Parse a `KEY=VALUE` claim. The value is read as JSON when possible, and as a string otherwise.
+pub fn parse_claim(claim: &str) -> Result<(String, Value), String> {
+    let Some((key, value)) = claim.split_once('=') else {
+        return Err(format!("claim must be KEY=VALUE: {claim}"));
+    };
+
+    let value = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+
+    Ok((key.to_owned(), value))
+}
+
+/// Client id of `client_secret_basic` authentication: `Basic base64(id:secret)`, with
+/// both parts form-urlencoded before encoding. The secret is not checked.
+fn basic_client_id(headers: &hyper::HeaderMap) -> Option<String> {
+    let credentials = headers
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (client_id, _) = credentials.split_once(':')?;
+
+    let client_id = client_id.replace('+', " ");
+    let client_id = percent_encoding::percent_decode_str(&client_id)
+        .decode_utf8()
+        .ok()?;
+    Some(client_id.into_owned()).filter(|client_id| !client_id.is_empty())
+}
+
+fn parse_form(input: &[u8]) -> HashMap<String, String> {
+    form_urlencoded::parse(input).into_owned().collect()
+}
+
+fn json_response(status: u16, value: &Value) -> Response<ProxyBody> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::CACHE_CONTROL, "no-store")
+        .body(full(value.to_string()))
+        .unwrap()
+}
+
+fn oauth_error(status: u16, error: &str, description: &str) -> Response<ProxyBody> {
+    json_response(
+        status,
+        &json!({ "error": error, "error_description": description }),
+    )
+}
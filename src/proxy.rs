@@ -1,30 +1,725 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use http_body_util::{BodyExt, Full};
-use hyper::body::Bytes;
-use hyper::{HeaderMap, Request, Response};
+use hyper::body::{Body, Bytes};
+use hyper::header::HeaderValue;
+use hyper::upgrade::OnUpgrade;
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use hyper_util::client::legacy::connect::HttpInfo;
+use hyper_util::rt::TokioIo;
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::constants::PROXY_HOST_HEADER;
+use crate::access_log::{AccessLog, AccessLogEntry, AccessLogFilter, AccessLogOutput};
+use crate::auth::AuthSimulation;
+use crate::body::{full, full_with_trailers, ProxyBody};
+use crate::capture::{Exchange, TrafficCapture};
+use crate::cli::CommandFlags;
+use crate::client::{self, ClientOptions, PoolSettings};
+use crate::clock;
+use crate::compression::ResponseCompression;
+use crate::constants::{
+    JWT_CLAIMS_HEADER, LATENCY_PROFILE_HEADER, PROXY_HOST_HEADER, RESET_AFTER_BYTES_HEADER,
+    SERVER_TIMING_HEADER,
+};
+use crate::context::{RequestContext, Route};
+use crate::csrf::{self, CsrfProtection};
+use crate::deadline::Deadline;
+use crate::error_page::{error_response, ErrorPages};
+use crate::fs_mock::FsMocks;
+use crate::git_sync::GitSync;
+use crate::header_limit::HeaderLimits;
+use crate::header_profile::{HeaderProfiles, NamedProfiles};
+use crate::jwt::JwtValidator;
+use crate::latency::LatencyProfile;
+use crate::lifecycle::{LifecycleEvent, LifecycleHooks};
+use crate::limiter::UpstreamLimiter;
+use crate::log_sink::LogSink;
+use crate::mirror::NatsMirror;
+use crate::oidc::OidcIssuer;
+use crate::readiness::Readiness;
+use crate::redact::Redactor;
+use crate::reset::ResetAfter;
+use crate::rotation::Rotation;
+use crate::signature::WebhookSignature;
+use crate::sigv4::SigV4Signer;
+use crate::snapshot::SnapshotStore;
+use crate::static_files::StaticFiles;
+use crate::stats;
+use crate::throttle::BandwidthLimits;
+use crate::timing::{self, UpstreamTiming};
+use crate::utility::UtilityRoutes;
+
+/// Wait before the first retry of failed startup steps, doubled after each retry
+const LOAD_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_LOAD_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Shared state for all proxied requests
+pub struct ProxyState {
+    pub client: reqwest::Client,
+    pub upstream_clients: HashMap<String, reqwest::Client>,
+    pub access_log: Option<Arc<AccessLog>>,
+    pub access_log_filter: AccessLogFilter,
+    pub capture: Option<Arc<TrafficCapture>>,
+    pub mirror: Option<NatsMirror>,
+    pub limiter: Option<UpstreamLimiter>,
+    pub hedge_delay: Option<Duration>,
+    pub max_response_body_size: Option<u64>,
+    pub oidc: Option<OidcIssuer>,
+    pub jwt: Option<JwtValidator>,
+    pub auth: Option<AuthSimulation>,
+    pub csrf: Option<CsrfProtection>,
+    pub signature_verifier: Option<WebhookSignature>,
+    pub signer: Option<WebhookSignature>,
+    pub sigv4: Option<SigV4Signer>,
+    pub snapshots: Option<SnapshotStore>,
+    pub stats_path: Option<String>,
+    pub verify_path: Option<String>,
+    pub ready_path: Option<String>,
+    pub readiness: Readiness,
+    pub unmatched_status: Option<u16>,
+    pub utility: Option<UtilityRoutes>,
+    pub fs_mocks: Option<FsMocks>,
+    pub lifecycle: Option<Arc<LifecycleHooks>>,
+    pub upgraded: bool,
+    pub mock_sync: Option<Arc<GitSync>>,
+    pub mock_sync_interval: Option<Duration>,
+    pub mock_sync_path: Option<String>,
+    pub clock_path: Option<String>,
+    pub static_files: Option<StaticFiles>,
+    pub compression: Option<ResponseCompression>,
+    pub slow_request_threshold: Option<Duration>,
+    pub server_timing: bool,
+    pub latency_profile: Option<LatencyProfile>,
+    pub bandwidth_limits: Option<BandwidthLimits>,
+    pub reset_after_bytes: Option<u64>,
+    pub redactor: Redactor,
+    pub error_pages: ErrorPages,
+    pub header_profiles: Option<HeaderProfiles>,
+    pub header_limits: HeaderLimits,
+}
+
+impl ProxyState {
+    pub fn new(flags: &CommandFlags) -> Self {
+        let lifecycle = (!flags.lifecycle_webhook.is_empty())
+            .then(|| Arc::new(LifecycleHooks::new(flags.lifecycle_webhook.clone())));
+
+        Self {
+            client: client::build_upstream_client(client_options(flags, None))
+                .expect("Failed to create a reqwest client"),
+            upstream_clients: flags
+                .upstream_pool
+                .iter()
+                .map(|(upstream, _)| upstream)
+                .chain(
+                    flags
+                        .upstream_ip_family
+                        .iter()
+                        .map(|(upstream, _)| upstream),
+                )
+                .chain(
+                    flags
+                        .upstream_outbound_bind
+                        .iter()
+                        .map(|(upstream, _)| upstream),
+                )
+                .chain(&flags.upstream_title_case_headers)
+                .map(|upstream| {
+                    let client =
+                        client::build_upstream_client(client_options(flags, Some(upstream)))
+                            .expect("Failed to create a reqwest client");
+                    (upstream.clone(), client)
+                })
+                .collect(),
+            access_log: access_log_output(flags)
+                .map(|output| AccessLog::start(output, flags.access_log_buffer)),
+            access_log_filter: AccessLogFilter::new(
+                &flags.access_log_exclude,
+                &flags.access_log_sample,
+            ),
+            capture: flags.capture_file.as_ref().map(|path| {
+                TrafficCapture::start(path, flags.capture_max_body_size, flags.capture_spill_size)
+                    .expect("Failed to create traffic capture file")
+            }),
+            mirror: flags.mirror_nats.clone().map(|address| {
+                NatsMirror::start(
+                    address,
+                    flags.mirror_nats_subject.clone(),
+                    flags.capture_max_body_size,
+                )
+            }),
+            limiter: flags.upstream_max_in_flight.map(|max_in_flight| {
+                UpstreamLimiter::new(max_in_flight, flags.upstream_queue_size)
+            }),
+            hedge_delay: flags.hedge_delay.map(Duration::from_millis),
+            max_response_body_size: flags.max_response_body_size,
+            oidc: flags.oidc_issuer.as_ref().map(|issuer| {
+                OidcIssuer::new(issuer, flags.oidc_claim.clone(), flags.oidc_token_ttl)
+                    .expect("Failed to create OIDC signing key")
+            }),
+            jwt: flags.jwt_jwks.as_ref().map(|jwks| {
+                JwtValidator::new(
+                    jwks.to_owned(),
+                    flags.jwt_issuer.clone(),
+                    flags.jwt_audience.clone(),
+                    flags.jwt_path.clone(),
+                )
+            }),
+            csrf: (!flags.csrf_origin.is_empty()
+                || flags.csrf_cookie.is_some()
+                || flags.cors_preflight)
+                .then(|| {
+                    CsrfProtection::new(
+                        flags.csrf_origin.clone(),
+                        flags.cors_preflight,
+                        flags.csrf_cookie.clone(),
+                        &flags.csrf_header,
+                        flags.csrf_path.clone(),
+                    )
+                }),
+            auth: (!flags.auth_route.is_empty()).then(|| {
+                AuthSimulation::new(flags.auth_route.clone(), flags.auth_expired_token.clone())
+            }),
+            signature_verifier: flags
+                .verify_signature_secret
+                .as_ref()
+                .map(|secret| WebhookSignature::new(flags.signature_style, secret.expose())),
+            signer: flags
+                .sign_secret
+                .as_ref()
+                .map(|secret| WebhookSignature::new(flags.signature_style, secret.expose())),
+            sigv4: flags
+                .sigv4_access_key
+                .as_ref()
+                .zip(flags.sigv4_secret_key.as_ref())
+                .map(|(access_key, secret_key)| {
+                    SigV4Signer::new(
+                        access_key.to_owned(),
+                        secret_key.expose().to_owned(),
+                        flags
+                            .sigv4_session_token
+                            .as_ref()
+                            .map(|token| token.expose().to_owned()),
+                        flags.sigv4_region.clone(),
+                        flags.sigv4_service.clone(),
+                        flags.sigv4_path.clone(),
+                    )
+                }),
+            snapshots: flags.snapshot_dir.as_ref().map(|dir| {
+                SnapshotStore::new(dir.to_owned(), flags.snapshot_update)
+                    .expect("Failed to create snapshot directory")
+            }),
+            compression: flags.compress_min_size.map(ResponseCompression::new),
+            stats_path: flags.stats_path.clone(),
+            verify_path: flags.verify_path.clone(),
+            ready_path: flags.ready_path.clone(),
+            readiness: Readiness::new(),
+            unmatched_status: flags.strict.then_some(flags.unmatched_status),
+            utility: flags.utility_prefix.as_deref().map(UtilityRoutes::new),
+            fs_mocks: flags.mock_dir.clone().map(|dir| {
+                FsMocks::new(
+                    dir,
+                    flags.mock_expect.clone(),
+                    &flags.mock_limit,
+                    flags.mock_env,
+                )
+            }),
+            mock_sync: flags.mock_git_repo.clone().zip(flags.mock_dir.clone()).map(
+                |(repo, dir)| {
+                    Arc::new(GitSync::new(
+                        repo,
+                        flags.mock_git_branch.clone(),
+                        dir,
+                        lifecycle.clone(),
+                    ))
+                },
+            ),
+            mock_sync_interval: (flags.mock_git_interval > 0)
+                .then(|| Duration::from_secs(flags.mock_git_interval)),
+            mock_sync_path: flags.mock_sync_path.clone(),
+            clock_path: flags.clock_path.clone(),
+            lifecycle,
+            upgraded: flags.is_uprade_mode(),
+            static_files: (!flags.static_routes.is_empty())
+                .then(|| StaticFiles::new(flags.static_routes.clone(), flags.static_listing)),
+            slow_request_threshold: flags.slow_request_threshold.map(Duration::from_millis),
+            server_timing: flags.server_timing,
+            latency_profile: flags.latency_profile,
+            reset_after_bytes: flags.reset_after_bytes,
+            bandwidth_limits: (flags.bandwidth_limit.is_some()
+                || !flags.bandwidth_route.is_empty())
+            .then(|| {
+                BandwidthLimits::new(
+                    flags.bandwidth_limit,
+                    flags.bandwidth_route.clone(),
+                    flags.bandwidth_burst,
+                )
+            }),
+            redactor: Redactor::new(
+                &flags.redact_header,
+                &flags.redact_json_path,
+                &flags.redact_pattern,
+            )
+            .expect("Failed to create redaction rules"),
+            error_pages: ErrorPages::load(&flags.error_page).expect("Failed to read error pages"),
+            header_profiles: (!flags.header_route.is_empty() || flags.mock_dir.is_some()).then(
+                || {
+                    HeaderProfiles::new(&flags.header_profile, &flags.header_route)
+                        .expect("Failed to create header profiles")
+                },
+            ),
+            header_limits: HeaderLimits::new(flags.max_headers, flags.max_header_size),
+        }
+    }
+
+    /// Load the JWKS, the mock repository, mock files and static directories, and become
+    /// ready once all of them have loaded. Failed steps are retried with backoff.
+    pub async fn load(&self) {
+        if self.jwt.is_some() {
+            self.readiness.start("jwks");
+        }
+        if self.mock_sync.is_some() {
+            self.readiness.start("mock repository");
+        }
+        if self.fs_mocks.is_some() {
+            self.readiness.start("mocks");
+        }
+        if self.static_files.is_some() {
+            self.readiness.start("static files");
+        }
+
+        if let (Some(mock_sync), Some(interval)) = (&self.mock_sync, self.mock_sync_interval) {
+            mock_sync.clone().spawn(interval);
+        }
+
+        let mut retry_delay = LOAD_RETRY_DELAY;
+        let mut failure_reported = false;
+        loop {
+            self.load_pending().await;
+            self.readiness.complete();
+            if self.readiness.is_ready() {
+                break;
+            }
+
+            if !failure_reported {
+                failure_reported = true;
+                if let Some(lifecycle) = &self.lifecycle {
+                    lifecycle
+                        .emit(LifecycleEvent::LoadFailed, self.readiness.report())
+                        .await;
+                }
+            }
+
+            log::info!("Retrying failed startup steps in {retry_delay:?}");
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(MAX_LOAD_RETRY_DELAY);
+        }
+
+        if let Some(lifecycle) = &self.lifecycle {
+            let event = if self.upgraded {
+                LifecycleEvent::UpgradeCompleted
+            } else {
+                LifecycleEvent::Started
+            };
+            lifecycle.emit(event, self.readiness.report()).await;
+        }
+    }
+
+    /// Run the startup steps that have not loaded yet
+    async fn load_pending(&self) {
+        if let Some(jwt) = self
+            .jwt
+            .as_ref()
+            .filter(|_| !self.readiness.is_loaded("jwks"))
+        {
+            let result = jwt.preload().await;
+            self.readiness.finish(
+                "jwks",
+                result
+                    .map(|keys| format!("{keys} keys"))
+                    .map_err(|error| error.to_string()),
+            );
+        }
+        if let Some(mock_sync) = self
+            .mock_sync
+            .as_ref()
+            .filter(|_| !self.readiness.is_loaded("mock repository"))
+        {
+            let result = mock_sync.sync().await;
+            self.readiness.finish(
+                "mock repository",
+                result.map(|commit| format!("commit {commit}")),
+            );
+        }
+        if let Some(fs_mocks) = self
+            .fs_mocks
+            .as_ref()
+            .filter(|_| !self.readiness.is_loaded("mocks"))
+        {
+            let result = fs_mocks.load().await;
+            self.readiness.finish(
+                "mocks",
+                result.map(|(files, invalid)| match invalid {
+                    0 => format!("{files} files"),
+                    invalid => format!("{files} files, {invalid} invalid"),
+                }),
+            );
+        }
+        if let Some(static_files) = self
+            .static_files
+            .as_ref()
+            .filter(|_| !self.readiness.is_loaded("static files"))
+        {
+            let result = static_files.load().await;
+            self.readiness.finish(
+                "static files",
+                result.map(|routes| format!("{routes} routes")),
+            );
+        }
+    }
+
+    /// Write out anything still buffered before the process exits
+    pub async fn flush(&self) {
+        if let Some(access_log) = &self.access_log {
+            access_log.flush().await;
+        }
+        if let Some(capture) = &self.capture {
+            capture.finish().await;
+        }
+    }
+
+    /// Compress a locally served response, if enabled
+    async fn compress(
+        &self,
+        response: Response<ProxyBody>,
+        request_headers: &HeaderMap,
+    ) -> Response<ProxyBody> {
+        match &self.compression {
+            Some(compression) => compression.apply(response, request_headers).await,
+            None => response,
+        }
+    }
+
+    /// Process exit code, non-zero if snapshot regressions were found, or unmatched
+    /// requests in strict mode
+    pub fn exit_code(&self) -> i32 {
+        let mut exit_code = 0;
+
+        if let Some(snapshots) = &self.snapshots {
+            if snapshots.regressions() > 0 {
+                log::error!("{} snapshot regressions found", snapshots.regressions());
+                exit_code = 1;
+            }
+        }
+
+        if let Some(fs_mocks) = &self.fs_mocks {
+            let violations = fs_mocks.violations();
+            for violation in &violations {
+                log::error!("Mock expectation not met: {violation}");
+            }
+            if !violations.is_empty() {
+                exit_code = 1;
+            }
+        }
+
+        let unmatched = stats::UNMATCHED.load(std::sync::atomic::Ordering::Relaxed);
+        if self.unmatched_status.is_some() && unmatched > 0 {
+            log::error!("{unmatched} requests were not matched");
+            exit_code = 1;
+        }
+
+        exit_code
+    }
+}
+
+/// Client options for the upstream, or for upstreams without their own when `None`
+fn client_options(flags: &CommandFlags, upstream: Option<&str>) -> ClientOptions {
+    let global_pool = PoolSettings {
+        max_idle: flags.upstream_pool_max_idle,
+        idle_timeout: flags.upstream_pool_idle_timeout,
+    };
+    let pool = flags
+        .upstream_pool
+        .iter()
+        .find(|(name, _)| Some(name.as_str()) == upstream)
+        .map_or(global_pool, |(_, pool)| pool.or(global_pool));
+    let ip_family = flags
+        .upstream_ip_family
+        .iter()
+        .find(|(name, _)| Some(name.as_str()) == upstream)
+        .map_or(flags.ip_family, |(_, ip_family)| *ip_family);
+    let bind = flags
+        .upstream_outbound_bind
+        .iter()
+        .find(|(name, _)| Some(name.as_str()) == upstream)
+        .map(|(_, bind)| bind.clone())
+        .or_else(|| flags.outbound_bind.clone());
+    let title_case_headers = flags.title_case_headers
+        || flags
+            .upstream_title_case_headers
+            .iter()
+            .any(|name| Some(name.as_str()) == upstream);
+
+    ClientOptions {
+        pool,
+        ip_family,
+        bind,
+        title_case_headers,
+    }
+}
+
+fn access_log_output(flags: &CommandFlags) -> Option<AccessLogOutput> {
+    if let Some(kind) = flags.access_log_sink {
+        let sink = LogSink::connect(kind, flags.syslog_address.as_deref())
+            .expect("Failed to connect access log sink");
+        return Some(AccessLogOutput::Sink(Arc::new(sink)));
+    }
+
+    flags.access_log.as_ref().map(|path| {
+        let rotation = Rotation::new(
+            flags.access_log_max_size,
+            flags.access_log_rotate_interval,
+            flags.access_log_keep,
+        );
+        AccessLogOutput::open(path, rotation).expect("Failed to open access log")
+    })
+}
 
 pub async fn handle_proxy_request(
+    request: Request<hyper::body::Incoming>,
+    state: Arc<ProxyState>,
+    client_addr: SocketAddr,
+) -> Result<Response<ProxyBody>, Infallible> {
+    let mut context = RequestContext::new(
+        &request,
+        client_addr,
+        state.latency_profile,
+        state.reset_after_bytes,
+    );
+    let uri = state.redactor.text(&request.uri().to_string()).into_owned();
+
+    let Ok(mut response) = forward_request(request, &state, &mut context).await;
+    state.error_pages.apply(&mut response);
+    let duration = context.received_at.elapsed();
+
+    if state
+        .slow_request_threshold
+        .is_some_and(|threshold| duration >= threshold)
+    {
+        let breakdown = response
+            .extensions()
+            .get::<UpstreamTiming>()
+            .map_or("no upstream response".to_owned(), UpstreamTiming::to_string);
+        log::warn!(
+            "Slow request #{}: {} {uri} via {} ({}) -> {} in {:.3}ms ({breakdown})",
+            context.id,
+            context.method,
+            context.upstream.as_deref().unwrap_or("-"),
+            context.route,
+            response.status().as_u16(),
+            timing::millis(duration),
+        );
+    }
+
+    if let Some(access_log) = state
+        .access_log
+        .as_ref()
+        .filter(|_| state.access_log_filter.allows(&context.path))
+    {
+        access_log.log(AccessLogEntry {
+            time: clock::now(),
+            client_addr,
+            method: context.method.clone(),
+            uri,
+            upstream: context.upstream.clone(),
+            status: response.status(),
+            bytes: response.body().size_hint().exact(),
+            duration,
+        });
+    }
+
+    if state.server_timing {
+        let server_timing =
+            UpstreamTiming::server_timing(response.extensions().get::<UpstreamTiming>(), duration);
+        if let Ok(server_timing) = HeaderValue::from_str(&server_timing) {
+            response
+                .headers_mut()
+                .append(SERVER_TIMING_HEADER, server_timing);
+        }
+    }
+
+    if let Some(header_profiles) = &state.header_profiles {
+        if matches!(context.route, Route::Mock | Route::Static) {
+            let named = response
+                .extensions_mut()
+                .remove::<NamedProfiles>()
+                .unwrap_or(NamedProfiles(Vec::new()));
+            header_profiles.apply(&context.path, &named.0, response.headers_mut());
+        }
+    }
+
+    if let Some(origin) = &context.cors_origin {
+        csrf::allow_origin(response.headers_mut(), origin);
+    }
+
+    // the virtual clock also governs the Date header
+    if clock::is_shifted() {
+        if let Ok(date) = HeaderValue::from_str(&httpdate::fmt_http_date(clock::now())) {
+            response.headers_mut().insert(hyper::header::DATE, date);
+        }
+    }
+
+    if let Some(latency_profile) = context.latency_profile {
+        response = latency_profile.apply(response).await;
+    }
+
+    if let Some(bandwidth_limits) = &state.bandwidth_limits {
+        response = bandwidth_limits.apply(response, &context.path);
+    }
+
+    if let Some(limit) = context.reset_after_bytes {
+        response = response.map(|body| ResetAfter::new(body, limit).boxed());
+    }
+
+    Ok(response)
+}
+
+async fn forward_request(
     mut request: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, Infallible> {
+    state: &ProxyState,
+    context: &mut RequestContext,
+) -> Result<Response<ProxyBody>, Infallible> {
+    if let Some(limit) = state.header_limits.check(&request) {
+        log::warn!("Rejected request with 431: {limit}");
+        return Ok(error_response(431, limit));
+    }
+
+    // nothing but the readiness endpoint and the mock issuer is served until startup
+    // loading is done; the JWKS may be loaded from the issuer itself
+    let is_ready_path = state.ready_path.as_deref() == Some(request.uri().path());
+    let is_issuer_path = !request.headers().contains_key(PROXY_HOST_HEADER)
+        && state
+            .oidc
+            .as_ref()
+            .is_some_and(|oidc| oidc.handles(request.uri().path()));
+    if !state.readiness.is_ready() && !is_ready_path && !is_issuer_path {
+        return Ok(error_response(
+            503,
+            "Not ready: startup loading has not finished",
+        ));
+    }
+
     // 1. get 'Proxy-Host' header from request
     let headers = request.headers_mut();
 
+    // the context has read the test-control headers, which are not for the upstream
+    headers.remove(LATENCY_PROFILE_HEADER);
+    headers.remove(RESET_AFTER_BYTES_HEADER);
+
     let Some(proxy_target) = headers.remove(PROXY_HOST_HEADER) else {
-        return Ok(Response::builder()
-            .status(400)
-            .body(Full::new(Bytes::from("Proxy-Host header is missing")))
-            .unwrap());
+        // requests without 'Proxy-Host' may target the built-in endpoints
+        if state.stats_path.as_deref() == Some(request.uri().path()) {
+            let mock_stats = match &state.fs_mocks {
+                Some(fs_mocks) => Some(fs_mocks.stats().await),
+                None => None,
+            };
+            context.route = Route::Stats;
+            return Ok(stats::response(mock_stats));
+        }
+
+        if is_ready_path {
+            context.route = Route::Ready;
+            return Ok(state.readiness.response());
+        }
+
+        if state.verify_path.as_deref() == Some(request.uri().path()) {
+            let near_misses = state.fs_mocks.as_ref().map(FsMocks::near_misses);
+            let violations = state
+                .fs_mocks
+                .as_ref()
+                .map(FsMocks::violations)
+                .unwrap_or_default();
+            context.route = Route::Verify;
+            return Ok(stats::verify_response(near_misses, violations));
+        }
+
+        if let Some(mock_sync) = &state.mock_sync {
+            if state.mock_sync_path.as_deref() == Some(request.uri().path()) {
+                context.route = Route::MockSync;
+                return Ok(mock_sync.handle_request(&request).await);
+            }
+        }
+
+        if state.clock_path.as_deref() == Some(request.uri().path()) {
+            context.route = Route::Clock;
+            return Ok(clock::handle_request(request).await);
+        }
+
+        if let Some(oidc) = &state.oidc {
+            if oidc.handles(request.uri().path()) {
+                context.route = Route::Oidc;
+                return Ok(oidc.handle_request(request).await);
+            }
+        }
+
+        // preflights carry no credentials, so they are answered before authentication
+        if let Some(csrf) = &state.csrf {
+            if let Some(response) = csrf.check(&request) {
+                return Ok(response);
+            }
+            context.cors_origin = csrf.cors_origin(request.uri().path(), request.headers());
+        }
+
+        if let Some(auth) = &state.auth {
+            if let Some(response) = auth.check(request.uri().path(), request.headers()) {
+                return Ok(response);
+            }
+        }
+
+        if let Some(utility) = &state.utility {
+            if utility.handles(request.uri().path()) {
+                context.route = Route::Utility;
+                return Ok(utility.handle_request(request, context.client_addr).await);
+            }
+        }
+
+        if let Some(fs_mocks) = &state.fs_mocks {
+            if let Some(response) = fs_mocks.handle_request(&request).await {
+                context.route = Route::Mock;
+                return Ok(state.compress(response, request.headers()).await);
+            }
+        }
+
+        if let Some(static_files) = &state.static_files {
+            if let Some(response) = static_files.handle_request(&request).await {
+                context.route = Route::Static;
+                return Ok(state.compress(response, request.headers()).await);
+            }
+        }
+
+        context.route = Route::Unmatched;
+        stats::UNMATCHED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let status = state.unmatched_status.unwrap_or(400);
+
+        if let Some(fs_mocks) = &state.fs_mocks {
+            let reason = fs_mocks.explain_miss(&request).await;
+            return Ok(error_response(
+                status,
+                format!("Proxy-Host header is missing, and no mock matched: {reason}"),
+            ));
+        }
+
+        return Ok(error_response(status, "Proxy-Host header is missing"));
     };
 
     let Ok(proxy_target) = proxy_target.to_str() else {
-        return Ok(Response::builder()
-            .status(400)
-            .body(Full::new(Bytes::from(
-                "Proxy-Host header is not a valid string",
-            )))
-            .unwrap());
+        return Ok(error_response(
+            400,
+            "Proxy-Host header is not a valid string",
+        ));
     };
 
     // 2. prepare request
@@ -36,7 +731,39 @@ pub async fn handle_proxy_request(
     let mut request_headers = HeaderMap::new();
     std::mem::swap(&mut request_headers, request.headers_mut());
 
-    // 2.3 generate request URI for proxy
+    let deadline = Deadline::from_headers(&request_headers);
+
+    // 2.3. check the bearer token on protected paths, passing its claims to the upstream
+    if let Some(validator) = &state.jwt {
+        // claims only ever come from the proxy
+        request_headers.remove(JWT_CLAIMS_HEADER);
+        if validator.applies_to(request.uri().path()) {
+            match validator.validate(&request_headers).await {
+                Ok(claims) => {
+                    log::debug!("Bearer token claims: {claims:?}");
+                    let claims =
+                        URL_SAFE_NO_PAD.encode(serde_json::Value::Object(claims).to_string());
+                    if let Ok(claims) = HeaderValue::from_str(&claims) {
+                        request_headers.insert(JWT_CLAIMS_HEADER, claims);
+                    }
+                }
+                Err(error) => {
+                    log::debug!("Rejected bearer token: {error}");
+                    let mut response = error_response(401, format!("Unauthorized: {error}"));
+                    if let Ok(challenge) = HeaderValue::from_str(&format!(
+                        "Bearer error=\"invalid_token\", error_description=\"{error}\""
+                    )) {
+                        response
+                            .headers_mut()
+                            .insert(hyper::header::WWW_AUTHENTICATE, challenge);
+                    }
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    // 2.4. generate request URI for proxy
     let request_uri = {
         let uri = request.uri();
         let path = uri.path();
@@ -55,56 +782,580 @@ pub async fn handle_proxy_request(
         request_uri
     };
 
-    // 2.4. get request body
-    let Ok(request_body) = request.into_body().collect().await.map(|body| {
-        let bytes = body.to_bytes().to_vec();
-        unsafe { String::from_utf8_unchecked(bytes) }
-    }) else {
-        return Ok(Response::builder()
-            .status(400)
-            .body(Full::new(Bytes::from("Failed to read request body")))
-            .unwrap());
+    let sigv4 = state
+        .sigv4
+        .as_ref()
+        .filter(|sigv4| sigv4.applies_to(request.uri().path()));
+
+    // an upgrade request keeps the client connection, to tunnel it after a 101
+    let client_upgrade =
+        is_upgrade_request(&request_headers).then(|| hyper::upgrade::on(&mut request));
+
+    // 2.5. get request body
+    let Ok(collected) = request.into_body().collect().await else {
+        return Ok(error_response(400, "Failed to read request body"));
     };
 
-    log::debug!("Proxying request to: {method} {request_uri}");
-    log::debug!("Proxying request headers: {request_headers:?}");
-    log::debug!("Proxying request body: {request_body}");
+    // 2.6. get request trailers, if the client sent any
+    let request_trailers = collected.trailers().cloned();
+
+    let request_body = collected.to_bytes();
+
+    // 2.7. verify the webhook signature of the body
+    if let Some(verifier) = &state.signature_verifier {
+        if let Err(error) = verifier.verify(&request_headers, &request_body) {
+            log::debug!("Rejected webhook signature: {error}");
+            return Ok(error_response(
+                401,
+                format!("Invalid webhook signature: {error}"),
+            ));
+        }
+    }
+
+    // 2.8. sign the body for the upstream
+    if let Some(signer) = &state.signer {
+        signer.sign(&mut request_headers, &request_body);
+    }
+
+    // 2.9. sign the request for AWS
+    if let Some(sigv4) = sigv4 {
+        if let Err(error) = sigv4.sign(&method, &request_uri, &mut request_headers, &request_body) {
+            return Ok(error_response(
+                500,
+                format!("Failed to sign request: {error}"),
+            ));
+        }
+    }
+
+    if log::log_enabled!(log::Level::Debug) {
+        let redactor = &state.redactor;
+        log::debug!(
+            "Proxying request #{} to: {method} {}",
+            context.id,
+            redactor.text(&request_uri)
+        );
+        log::debug!(
+            "Proxying request headers: {:?}",
+            redactor.headers(&request_headers)
+        );
+        log::debug!(
+            "Proxying request body: {}",
+            String::from_utf8_lossy(&redactor.body(&request_body))
+        );
+        log::debug!(
+            "Proxying request trailers: {:?}",
+            request_trailers
+                .as_ref()
+                .map(|trailers| redactor.headers(trailers))
+        );
+    }
 
     // 3. send request to proxy
-    let Ok(client) = reqwest::ClientBuilder::new().build() else {
-        return Ok(Response::builder()
-            .status(400)
-            .body(Full::new(Bytes::from("Failed to create a reqwest client")))
-            .unwrap());
+
+    // 3.1. wait for a free slot on the upstream
+    let queued_at = Instant::now();
+    let _permit = match &state.limiter {
+        Some(limiter) => {
+            let Ok(permit) = limiter.acquire(proxy_target).await else {
+                log::warn!("Too many in-flight requests to upstream: {proxy_target}");
+                return Ok(error_response(
+                    503,
+                    "Too many in-flight requests to upstream",
+                ));
+            };
+            Some(permit)
+        }
+        None => None,
     };
 
-    let proxy_request = client
-        .request(method, request_uri)
-        .body(request_body)
+    let mut upstream_timing = UpstreamTiming {
+        queue: queued_at.elapsed(),
+        ..Default::default()
+    };
+
+    let _in_flight = stats::UPSTREAM_IN_FLIGHT.track();
+
+    // 3.2. enforce the client's deadline and pass the remaining budget upstream
+    let timeout = match deadline {
+        Some(deadline) => {
+            let elapsed = context.received_at.elapsed();
+            let Some(remaining) = deadline.remaining(elapsed) else {
+                return Ok(error_response(504, "Request deadline exceeded"));
+            };
+            deadline.write_headers(&mut request_headers, elapsed);
+            Some(remaining)
+        }
+        None => None,
+    };
+
+    let snapshot_key = state
+        .snapshots
+        .as_ref()
+        .map(|snapshots| snapshots.key(&method, &request_uri, &request_body));
+
+    // the request is moved into the client, so keep what the capture and the mirror need
+    let captured_request = (state.capture.is_some() || state.mirror.is_some())
+        .then(|| (request_headers.clone(), request_body.clone()));
+
+    let mut proxy_request = state
+        .upstream_clients
+        .get(proxy_target)
+        .unwrap_or(&state.client)
+        .request(method.clone(), &request_uri)
         .headers(request_headers);
 
-    let proxy_result = proxy_request.send().await;
+    proxy_request = match request_trailers {
+        Some(trailers) => proxy_request.body(reqwest::Body::wrap(
+            Full::new(request_body).with_trailers(std::future::ready(Some(Ok(trailers)))),
+        )),
+        None => proxy_request.body(request_body),
+    };
+
+    if let Some(timeout) = timeout {
+        proxy_request = proxy_request.timeout(timeout);
+    }
+
+    context.route = Route::Upstream;
+    let sent_at = Instant::now();
+    let proxy_result = match state.hedge_delay {
+        Some(delay) if method.is_idempotent() && client_upgrade.is_none() => {
+            let limiter = state
+                .limiter
+                .as_ref()
+                .map(|limiter| (limiter, proxy_target));
+            send_hedged(proxy_request, delay, limiter).await
+        }
+        _ => proxy_request.send().await,
+    };
 
     // 4. return response from proxy to client
     match proxy_result {
+        Ok(response) if response.status() == StatusCode::SWITCHING_PROTOCOLS => {
+            match client_upgrade {
+                Some(client_upgrade) => Ok(tunnel(response, client_upgrade, context.id)),
+                None => Ok(error_response(
+                    502,
+                    "Upstream switched protocols without an upgrade request",
+                )),
+            }
+        }
         Ok(response) => {
-            let mut response_builder = Response::builder().status(response.status());
+            upstream_timing.ttfb = sent_at.elapsed();
+            let setup = stats::UPSTREAM_CONNECTIONS.record(
+                proxy_target,
+                response
+                    .extensions()
+                    .get::<HttpInfo>()
+                    .map(HttpInfo::local_addr),
+            );
+            // a connection opened before the request was sent was not waited for
+            if let Some(setup) = setup.filter(|setup| setup.ready_at > sent_at) {
+                upstream_timing.add_connection_setup(setup);
+            }
+            let body_started_at = Instant::now();
+
+            let (mut parts, upstream_body) = upstream_head(response);
+            let status = parts.status;
+            let content_length = parts
+                .headers
+                .get(hyper::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
 
-            let headers = response_builder.headers_mut().unwrap();
+            let (body, trailers) = match read_response_body(
+                upstream_body,
+                content_length,
+                state.max_response_body_size,
+            )
+            .await
+            {
+                Ok(body) => body,
+                Err(ResponseBodyError::TooLarge(limit)) => {
+                    log::warn!("Upstream response body exceeds the limit of {limit} bytes");
+                    return Ok(error_response(
+                        502,
+                        format!("Upstream response body exceeds the limit of {limit} bytes",),
+                    ));
+                }
+                Err(ResponseBodyError::Upstream(error)) if error.is_timeout() => {
+                    return Ok(error_response(504, "Request deadline exceeded"));
+                }
+                Err(ResponseBodyError::Upstream(error)) => {
+                    return Ok(error_response(
+                        502,
+                        format!("Failed to read response body: {error:?}",),
+                    ));
+                }
+            };
 
-            for (key, value) in response.headers() {
-                headers.insert(key, value.clone());
+            upstream_timing.body = body_started_at.elapsed();
+
+            if let (Some(snapshots), Some(snapshot_key)) = (&state.snapshots, &snapshot_key) {
+                snapshots
+                    .check(
+                        snapshot_key,
+                        &method,
+                        &state.redactor.text(&request_uri),
+                        status,
+                        &state.redactor.body(&body),
+                    )
+                    .await;
             }
 
-            let body = response.bytes().await.unwrap();
+            if let Some((captured_headers, captured_body)) = &captured_request {
+                let exchange = Exchange {
+                    started_at: context.started_at,
+                    duration: context.received_at.elapsed(),
+                    timing: upstream_timing,
+                    method: &method,
+                    url: &request_uri,
+                    request_headers: captured_headers,
+                    request_body: captured_body,
+                    status,
+                    response_headers: &parts.headers,
+                    response_body: &body,
+                };
+                if let Some(capture) = &state.capture {
+                    capture.record(&exchange, &state.redactor).await;
+                }
+                if let Some(mirror) = &state.mirror {
+                    mirror.publish(&exchange, &state.redactor);
+                }
+            }
 
-            Ok(response_builder.body(Full::new(body)).unwrap())
+            let body = match trailers {
+                Some(trailers) => full_with_trailers(body, trailers),
+                None => full(body),
+            };
+
+            parts.extensions.insert(upstream_timing);
+
+            Ok(Response::from_parts(parts, body))
+        }
+        Err(error) if error.is_timeout() => Ok(error_response(504, "Request deadline exceeded")),
+        Err(error) => Ok(error_response(
+            500,
+            format!("Failed to send request: {error:?}",),
+        )),
+    }
+}
+
+/// Take the upstream head as-is, without copying headers, so repeated ones like
+/// `Set-Cookie` stay repeated
+fn upstream_head(response: reqwest::Response) -> (hyper::http::response::Parts, reqwest::Body) {
+    let (mut parts, body) = Response::from(response).into_parts();
+    parts.version = hyper::Version::default();
+    (parts, body)
+}
+
+enum ResponseBodyError {
+    TooLarge(u64),
+    Upstream(reqwest::Error),
+}
+
+/// Read the upstream response body and trailers,
+/// giving up as soon as the body grows beyond `limit` bytes.
+///
+/// Chunks are kept as received, and only copied into one buffer when there is more than one.
+async fn read_response_body(
+    mut upstream_body: reqwest::Body,
+    content_length: Option<u64>,
+    limit: Option<u64>,
+) -> Result<(Bytes, Option<HeaderMap>), ResponseBodyError> {
+    let limit = limit.unwrap_or(u64::MAX);
+
+    if content_length.is_some_and(|length| length > limit) {
+        return Err(ResponseBodyError::TooLarge(limit));
+    }
+
+    let mut chunks: Vec<Bytes> = Vec::new();
+    let mut length = 0u64;
+    let mut trailers = None;
+
+    while let Some(frame) = upstream_body.frame().await {
+        let frame = frame.map_err(ResponseBodyError::Upstream)?;
+
+        match frame.into_data() {
+            Ok(chunk) => {
+                length += chunk.len() as u64;
+                if length > limit {
+                    return Err(ResponseBodyError::TooLarge(limit));
+                }
+                chunks.push(chunk);
+            }
+            Err(frame) => {
+                if let Ok(frame_trailers) = frame.into_trailers() {
+                    trailers = Some(frame_trailers);
+                }
+            }
         }
-        Err(error) => Ok(Response::builder()
-            .status(500)
-            .body(Full::new(Bytes::from(format!(
-                "Failed to send request: {error:?}",
-            ))))
-            .unwrap()),
+    }
+
+    let body = match chunks.len() {
+        0 => Bytes::new(),
+        1 => chunks.pop().unwrap(),
+        _ => {
+            let mut body = Vec::with_capacity(length as usize);
+            for chunk in &chunks {
+                body.extend_from_slice(chunk);
+            }
+            Bytes::from(body)
+        }
+    };
+
+    Ok((body, trailers))
+}
+
+/// Requests with `Connection: upgrade` and an `Upgrade` protocol, like WebSocket or h2c
+fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    headers.contains_key(hyper::header::UPGRADE)
+        && headers
+            .get_all(hyper::header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Answer with the upstream's 101, then pump raw bytes both ways once both connections
+/// have switched protocols
+fn tunnel(response: reqwest::Response, client_upgrade: OnUpgrade, id: u64) -> Response<ProxyBody> {
+    let mut switching = Response::new(full(Bytes::new()));
+    *switching.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    *switching.headers_mut() = response.headers().clone();
+
+    tokio::spawn(async move {
+        let mut upstream = match response.upgrade().await {
+            Ok(upstream) => upstream,
+            Err(error) => {
+                log::warn!("Failed to upgrade upstream connection of request #{id}: {error}");
+                return;
+            }
+        };
+        let mut client = match client_upgrade.await {
+            Ok(client) => TokioIo::new(client),
+            Err(error) => {
+                log::warn!("Failed to upgrade client connection of request #{id}: {error}");
+                return;
+            }
+        };
+
+        match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            Ok((sent, received)) => log::debug!(
+                "Tunnel of request #{id} closed after {sent} bytes sent and {received} received"
+            ),
+            Err(error) => log::debug!("Tunnel of request #{id} failed: {error}"),
+        }
+    });
+
+    switching
+}
+
+/// Send the request, and if no response arrived within `delay`, send a duplicate.
+/// Whichever succeeds first is returned. The duplicate takes its own limiter slot, and
+/// is not sent when the upstream has none free.
+async fn send_hedged(
+    request: reqwest::RequestBuilder,
+    delay: Duration,
+    limiter: Option<(&UpstreamLimiter, &str)>,
+) -> reqwest::Result<reqwest::Response> {
+    let Some(hedged_request) = request.try_clone() else {
+        return request.send().await;
+    };
+
+    let primary = request.send();
+    tokio::pin!(primary);
+
+    tokio::select! {
+        result = &mut primary => return result,
+        _ = tokio::time::sleep(delay) => {}
+    }
+
+    let _hedge_permit = match limiter {
+        Some((limiter, upstream)) => match limiter.try_acquire(upstream) {
+            Some(permit) => Some(permit),
+            None => {
+                log::debug!("No response after {delay:?}, but no free slot on {upstream} to hedge");
+                return primary.await;
+            }
+        },
+        None => None,
+    };
+
+    log::debug!("No response after {delay:?}, sending hedged request");
+
+    let hedged = hedged_request.send();
+    tokio::pin!(hedged);
+
+    tokio::select! {
+        result = &mut primary => match result {
+            Ok(response) => Ok(response),
+            Err(_) => hedged.await,
+        },
+        result = &mut hedged => match result {
+            Ok(response) => Ok(response),
+            Err(_) => primary.await,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use hyper::header::{COOKIE, SET_COOKIE};
+    use tokio::net::TcpListener;
+
+    use crate::cli::Command;
+
+    /// Serve the proxy with the command line arguments on the listener
+    fn serve(listener: TcpListener, args: &[&str]) -> Arc<ProxyState> {
+        let flags = Command::parse_from([&["affogato"], args].concat()).value;
+        let state = Arc::new(ProxyState::new(&flags));
+
+        let serving_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, client_addr)) = listener.accept().await {
+                let state = serving_state.clone();
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(
+                    TokioIo::new(stream),
+                    hyper::service::service_fn(move |request| {
+                        handle_proxy_request(request, state.clone(), client_addr)
+                    }),
+                ));
+            }
+        });
+
+        state
+    }
+
+    #[tokio::test]
+    async fn loads_the_jwks_from_its_own_issuer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let jwks = format!("{issuer}/.well-known/jwks.json");
+        let state = serve(listener, &["--oidc-issuer", &issuer, "--jwt-jwks", &jwks]);
+
+        tokio::time::timeout(Duration::from_secs(5), state.load())
+            .await
+            .expect("startup loading did not finish");
+
+        assert!(state.readiness.is_ready());
+        assert!(state.readiness.is_loaded("jwks"));
+    }
+
+    #[test]
+    fn upstream_head_keeps_every_set_cookie() {
+        let upstream = Response::builder()
+            .header(SET_COOKIE, "session=abc; HttpOnly")
+            .header(SET_COOKIE, "theme=dark")
+            .header(SET_COOKIE, "lang=en; Path=/")
+            .body("")
+            .unwrap();
+
+        let (parts, _) = upstream_head(reqwest::Response::from(upstream));
+
+        let cookies: Vec<_> = parts.headers.get_all(SET_COOKIE).iter().collect();
+        assert_eq!(
+            cookies,
+            ["session=abc; HttpOnly", "theme=dark", "lang=en; Path=/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_headers_survive_the_round_trip() {
+        // the upstream sets two cookies, and answers with the cookies it got
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = upstream.accept().await {
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(
+                    TokioIo::new(stream),
+                    hyper::service::service_fn(|request: Request<hyper::body::Incoming>| {
+                        let cookies: Vec<_> = request
+                            .headers()
+                            .get_all(COOKIE)
+                            .iter()
+                            .map(|value| value.to_str().unwrap().to_owned())
+                            .collect();
+                        let response = Response::builder()
+                            .header(SET_COOKIE, "session=abc; HttpOnly")
+                            .header(SET_COOKIE, "theme=dark")
+                            .body(full(cookies.join(" | ")));
+                        std::future::ready(response)
+                    }),
+                ));
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        serve(listener, &[]).load().await;
+
+        let response = reqwest::Client::new()
+            .get(format!("http://{proxy_addr}/"))
+            .header(PROXY_HOST_HEADER, format!("http://{upstream_addr}"))
+            .header(COOKIE, "a=1")
+            .header(COOKIE, "b=2")
+            .send()
+            .await
+            .unwrap();
+
+        let set_cookies: Vec<_> = response.headers().get_all(SET_COOKIE).iter().collect();
+        assert_eq!(set_cookies, ["session=abc; HttpOnly", "theme=dark"]);
+        assert_eq!(response.text().await.unwrap(), "a=1 | b=2");
+    }
+
+    /// Requests a slow upstream got for one GET through the proxy
+    async fn hedged_upstream_requests(max_in_flight: &str) -> usize {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = upstream.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(
+                    TokioIo::new(stream),
+                    hyper::service::service_fn(move |_: Request<hyper::body::Incoming>| {
+                        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        async {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            Response::builder().body(full("slow"))
+                        }
+                    }),
+                ));
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        serve(
+            listener,
+            &[
+                "--hedge-delay",
+                "20",
+                "--upstream-max-in-flight",
+                max_in_flight,
+            ],
+        )
+        .load()
+        .await;
+
+        let response = reqwest::Client::new()
+            .get(format!("http://{proxy_addr}/"))
+            .header(PROXY_HOST_HEADER, format!("http://{upstream_addr}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "slow");
+
+        received.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn hedges_only_with_a_free_upstream_slot() {
+        assert_eq!(hedged_upstream_requests("1").await, 1);
+        assert_eq!(hedged_upstream_requests("2").await, 2);
     }
 }
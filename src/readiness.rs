@@ -0,0 +1,112 @@
+use hyper::Response;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::body::{full, ProxyBody};
+
+/// Startup loading progress. Until every step has loaded, the proxy answers 503.
+/// Failed steps are retried by the loader, and the proxy becomes ready once they load.
+pub struct Readiness {
+    ready: AtomicBool,
+    steps: Mutex<Vec<Step>>,
+}
+
+struct Step {
+    name: &'static str,
+    state: StepState,
+}
+
+enum StepState {
+    Loading,
+    Loaded(String),
+    Failed(String),
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self {
+            ready: AtomicBool::new(false),
+            steps: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn start(&self, name: &'static str) {
+        self.steps.lock().unwrap().push(Step {
+            name,
+            state: StepState::Loading,
+        });
+    }
+
+    /// Record the outcome of a started step, with a short description of what was loaded
+    pub fn finish(&self, name: &'static str, result: Result<String, String>) {
+        match &result {
+            Ok(detail) => log::info!("Loaded {name}: {detail}"),
+            Err(error) => log::error!("Failed to load {name}: {error}"),
+        }
+
+        let mut steps = self.steps.lock().unwrap();
+        if let Some(step) = steps.iter_mut().find(|step| step.name == name) {
+            step.state = match result {
+                Ok(detail) => StepState::Loaded(detail),
+                Err(error) => StepState::Failed(error),
+            };
+        }
+    }
+
+    /// Whether the step has loaded, so it needs no retry
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.steps
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|step| step.name == name && matches!(step.state, StepState::Loaded(_)))
+    }
+
+    /// Ready once every step has loaded; a failed step keeps the proxy unready until a
+    /// retry loads it
+    pub fn complete(&self) {
+        let steps = self.steps.lock().unwrap();
+        if steps
+            .iter()
+            .all(|step| matches!(step.state, StepState::Loaded(_)))
+            && !self.ready.swap(true, Ordering::AcqRel)
+        {
+            log::info!("Ready to accept traffic");
+        }
+    }
+
+    /// Whether the proxy is ready, and the state of each step
+    pub fn report(&self) -> Value {
+        let steps: Vec<Value> = self
+            .steps
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|step| match &step.state {
+                StepState::Loading => json!({"name": step.name, "state": "loading"}),
+                StepState::Loaded(detail) => {
+                    json!({"name": step.name, "state": "loaded", "detail": detail})
+                }
+                StepState::Failed(error) => {
+                    json!({"name": step.name, "state": "failed", "error": error})
+                }
+            })
+            .collect();
+
+        json!({"ready": self.is_ready(), "steps": steps})
+    }
+
+    /// 200 when ready, 503 otherwise, with the state of each step
+    pub fn response(&self) -> Response<ProxyBody> {
+        Response::builder()
+            .status(if self.is_ready() { 200 } else { 503 })
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(full(self.report().to_string()))
+            .unwrap()
+    }
+}
@@ -0,0 +1,204 @@
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::HeaderMap;
+use regex::Regex;
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Replacement for redacted values
+const REDACTED: &str = "[REDACTED]";
+
+/// Headers that are always redacted
+const DEFAULT_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// Masks secrets and personal data before anything is logged or recorded.
+///
+/// Header values are redacted by name, JSON bodies by field path (dot separated,
+/// `*` matching any key or index), and any text matching one of the patterns.
+pub struct Redactor {
+    headers: Vec<HeaderName>,
+    json_paths: Vec<Vec<String>>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(
+        headers: &[String],
+        json_paths: &[String],
+        patterns: &[String],
+    ) -> Result<Self, String> {
+        let headers = DEFAULT_HEADERS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(headers.iter().cloned())
+            .map(|name| {
+                HeaderName::try_from(name.as_str())
+                    .map_err(|_| format!("invalid header name: {name}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let json_paths = json_paths
+            .iter()
+            .map(|path| path.split('.').map(str::to_owned).collect())
+            .collect();
+
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|error| format!("invalid pattern {pattern}: {error}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            headers,
+            json_paths,
+            patterns,
+        })
+    }
+
+    /// Copy of the headers with sensitive values masked
+    pub fn headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut redacted = headers.clone();
+        for name in &self.headers {
+            if let hyper::header::Entry::Occupied(mut entry) = redacted.entry(name) {
+                for value in entry.iter_mut() {
+                    *value = HeaderValue::from_static(REDACTED);
+                }
+            }
+        }
+        redacted
+    }
+
+    /// Text with every pattern match masked
+    pub fn text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            if let Cow::Owned(replaced) = pattern.replace_all(&text, REDACTED) {
+                text = Cow::Owned(replaced);
+            }
+        }
+        text
+    }
+
+    /// Body with JSON fields and pattern matches masked. Binary bodies are left as they are.
+    pub fn body<'a>(&self, body: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut body = Cow::Borrowed(body);
+
+        if !self.json_paths.is_empty() {
+            if let Ok(mut value) = serde_json::from_slice::<Value>(&body) {
+                let mut redacted = false;
+                for path in &self.json_paths {
+                    redacted |= redact_json(&mut value, path);
+                }
+                if redacted {
+                    body = Cow::Owned(value.to_string().into_bytes());
+                }
+            }
+        }
+
+        if let Ok(text) = std::str::from_utf8(&body) {
+            if let Cow::Owned(text) = self.text(text) {
+                body = Cow::Owned(text.into_bytes());
+            }
+        }
+
+        body
+    }
+}
+
+/// Mask the fields at `path`, looking through arrays on the way. Returns whether anything was masked.
+fn redact_json(value: &mut Value, path: &[String]) -> bool {
+    let Some((segment, rest)) = path.split_first() else {
+        *value = Value::String(REDACTED.to_owned());
+        return true;
+    };
+
+    match value {
+        Value::Object(map) => {
+            let mut redacted = false;
+            for (key, child) in map.iter_mut() {
+                if segment == "*" || key == segment {
+                    redacted |= redact_json(child, rest);
+                }
+            }
+            redacted
+        }
+        Value::Array(items) => match segment.parse::<usize>() {
+            Ok(index) => items
+                .get_mut(index)
+                .is_some_and(|item| redact_json(item, rest)),
+            Err(_) if segment == "*" => items
+                .iter_mut()
+                .fold(false, |redacted, item| redact_json(item, rest) | redacted),
+            Err(_) => items
+                .iter_mut()
+                .fold(false, |redacted, item| redact_json(item, path) | redacted),
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(json_paths: &[&str], patterns: &[&str]) -> Redactor {
+        let strings = |values: &[&str]| {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+        };
+        Redactor::new(
+            &["x-api-key".to_owned()],
+            &strings(json_paths),
+            &strings(patterns),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn masks_default_and_configured_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("set-cookie", HeaderValue::from_static("a=1"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2"));
+        headers.insert("x-api-key", HeaderValue::from_static("key"));
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+
+        let redacted = redactor(&[], &[]).headers(&headers);
+        let cookies: Vec<_> = redacted.get_all("set-cookie").iter().collect();
+        assert_eq!(cookies, [REDACTED, REDACTED]);
+        assert_eq!(redacted["x-api-key"], REDACTED);
+        assert_eq!(redacted["accept"], "*/*");
+    }
+
+    #[test]
+    fn masks_json_paths_through_arrays_and_wildcards() {
+        let redactor = redactor(&["users.password", "cards.*.number", "meta.*"], &[]);
+        let body = br#"{"users":[{"name":"a","password":"p1"},{"password":"p2"}],"cards":[{"number":"4242"}],"meta":{"ip":"1.2.3.4"},"id":7}"#;
+
+        let redacted: Value = serde_json::from_slice(&redactor.body(body)).unwrap();
+        assert_eq!(
+            redacted,
+            serde_json::json!({
+                "users": [{"name": "a", "password": REDACTED}, {"password": REDACTED}],
+                "cards": [{"number": REDACTED}],
+                "meta": {"ip": REDACTED},
+                "id": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn masks_patterns_and_leaves_other_bodies_alone() {
+        let redactor = redactor(&["token"], &[r"\d{4}-\d{4}"]);
+        assert_eq!(redactor.text("card 1234-5678 ok"), "card [REDACTED] ok");
+        assert!(matches!(redactor.body(b"no match"), Cow::Borrowed(_)));
+        assert!(matches!(redactor.body(&[0xff, 0xfe]), Cow::Borrowed(_)));
+        assert!(Redactor::new(&[], &[], &["(".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bench;
+use crate::capture::TRUNCATED_MARKER;
+use crate::cli::ReplayFlags;
+use crate::client;
+use crate::clock;
+use crate::constants::PROXY_HOST_HEADER;
+
+#[derive(Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntry {
+    started_date_time: String,
+    request: HarRequest,
+    response: Option<HarResponse>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct HarPostData {
+    #[serde(default)]
+    text: String,
+    encoding: Option<String>,
+    comment: Option<String>,
+    /// Body stored in its own file, relative to the recording
+    #[serde(rename = "_file")]
+    file: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HarResponse {
+    status: u16,
+}
+
+/// A recorded request, ready to be sent again
+struct ReplayRequest {
+    offset: Duration,
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    recorded_status: Option<u16>,
+}
+
+enum ReplayResult {
+    Completed {
+        latency: Duration,
+        status: u16,
+        recorded_status: Option<u16>,
+    },
+    Failed,
+}
+
+/// Send the requests of a HAR recording to `flags.base_url`, spaced like they were recorded
+/// (divided by `flags.speed`), and print how the responses compare to the recorded ones.
+pub async fn run(flags: ReplayFlags) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let recording = std::fs::read(&flags.recording)?;
+    let har: Har = serde_json::from_slice(&recording)?;
+    let requests = load_requests(har, &flags)?;
+    if requests.is_empty() {
+        println!("No requests in {}", flags.recording.display());
+        return Ok(());
+    }
+
+    let client = client::build_client()?;
+
+    println!(
+        "Replaying {} requests from {} against {} at {}x speed",
+        requests.len(),
+        flags.recording.display(),
+        flags.base_url,
+        flags.speed
+    );
+
+    let started_at = Instant::now();
+
+    let mut tasks = Vec::with_capacity(requests.len());
+    for request in requests {
+        if flags.speed > 0.0 {
+            let due = request.offset.div_f64(flags.speed);
+            tokio::time::sleep_until((started_at + due).into()).await;
+        }
+
+        let client = client.clone();
+        tasks.push(tokio::spawn(async move {
+            let sent_at = Instant::now();
+            let result = client
+                .request(request.method.clone(), &request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .send()
+                .await;
+
+            let result = match result {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    match response.bytes().await {
+                        Ok(_) => ReplayResult::Completed {
+                            latency: sent_at.elapsed(),
+                            status,
+                            recorded_status: request.recorded_status,
+                        },
+                        Err(_) => ReplayResult::Failed,
+                    }
+                }
+                Err(_) => ReplayResult::Failed,
+            };
+
+            if let ReplayResult::Completed {
+                status,
+                recorded_status: Some(recorded),
+                ..
+            } = result
+            {
+                if status != recorded {
+                    println!(
+                        "{} {}: status {status} (recorded {recorded})",
+                        request.method, request.url
+                    );
+                }
+            }
+
+            result
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    let mut statuses = BTreeMap::<u16, usize>::new();
+    let mut errors = 0;
+    let mut mismatches = 0;
+    for task in tasks {
+        match task.await? {
+            ReplayResult::Completed {
+                latency,
+                status,
+                recorded_status,
+            } => {
+                latencies.push(latency);
+                *statuses.entry(status).or_default() += 1;
+                if recorded_status.is_some_and(|recorded| recorded != status) {
+                    mismatches += 1;
+                }
+            }
+            ReplayResult::Failed => errors += 1,
+        }
+    }
+
+    let elapsed = started_at.elapsed();
+    latencies.sort();
+
+    let completed = latencies.len();
+    println!();
+    println!("Completed:  {completed} in {elapsed:.2?}");
+    println!("Errors:     {errors}");
+    println!("Mismatches: {mismatches}");
+
+    if completed > 0 {
+        println!();
+        println!("Latency");
+        for (label, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+            println!(
+                "  {label:<6}{:.2?}",
+                bench::percentile(&latencies, quantile)
+            );
+        }
+        println!("  {:<6}{:.2?}", "max", latencies[completed - 1]);
+    }
+
+    println!();
+    println!("Status codes");
+    for (status, count) in &statuses {
+        println!("  {status}: {count}");
+    }
+
+    Ok(())
+}
+
+/// Requests of the recording in the order they were sent, pointed at the base URL
+fn load_requests(
+    har: Har,
+    flags: &ReplayFlags,
+) -> Result<Vec<ReplayRequest>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut entries: Vec<(SystemTime, HarEntry)> = har
+        .log
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let started_at = clock::parse_rfc3339(&entry.started_date_time)
+                .ok_or_else(|| format!("invalid startedDateTime: {}", entry.started_date_time))?;
+            Ok((started_at, entry))
+        })
+        .collect::<Result<_, String>>()?;
+    entries.sort_by_key(|(started_at, _)| *started_at);
+
+    let Some((first, _)) = entries.first() else {
+        return Ok(Vec::new());
+    };
+    let first = *first;
+
+    let base_url = flags.base_url.trim_end_matches('/');
+
+    entries
+        .into_iter()
+        .map(|(started_at, entry)| {
+            let request = entry.request;
+            let method = Method::from_str(&request.method)?;
+
+            let mut headers = HeaderMap::new();
+            for header in &request.headers {
+                let name = HeaderName::from_str(&header.name)?;
+                // the client sets these for the new target and body
+                if matches!(
+                    name,
+                    hyper::header::HOST
+                        | hyper::header::CONTENT_LENGTH
+                        | hyper::header::TRANSFER_ENCODING
+                        | hyper::header::CONNECTION
+                ) {
+                    continue;
+                }
+                headers.append(name, HeaderValue::from_str(&header.value)?);
+            }
+            if let Some(proxy_host) = &flags.proxy_host {
+                headers.insert(PROXY_HOST_HEADER, HeaderValue::from_str(proxy_host)?);
+            }
+
+            if let Some(comment) = request
+                .post_data
+                .as_ref()
+                .and_then(|post_data| post_data.comment.as_deref())
+                .filter(|comment| comment.starts_with(TRUNCATED_MARKER))
+            {
+                println!(
+                    "{method} {}: recorded body was {comment}, sending it as recorded",
+                    request.url
+                );
+            }
+
+            let body = match request.post_data {
+                Some(HarPostData {
+                    file: Some(file), ..
+                }) => {
+                    let dir = flags.recording.parent().unwrap_or(Path::new(""));
+                    std::fs::read(dir.join(&file))
+                        .map_err(|error| format!("recorded body {file}: {error}"))?
+                }
+                Some(post_data) if post_data.encoding.as_deref() == Some("base64") => {
+                    STANDARD.decode(post_data.text)?
+                }
+                Some(post_data) => post_data.text.into_bytes(),
+                None => Vec::new(),
+            };
+
+            Ok(ReplayRequest {
+                offset: started_at.duration_since(first).unwrap_or_default(),
+                method,
+                url: format!("{base_url}{}", path_and_query(&request.url)),
+                headers,
+                body,
+                recorded_status: entry.response.map(|response| response.status),
+            })
+        })
+        .collect()
+}
+
+/// Path and query of an absolute URL
+fn path_and_query(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    match without_scheme.find('/') {
+        Some(index) => &without_scheme[index..],
+        None => "/",
+    }
+}
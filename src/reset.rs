@@ -0,0 +1,83 @@
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::HeaderMap;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use crate::body::{BoxError, ProxyBody};
+use crate::constants::RESET_AFTER_BYTES_HEADER;
+
+/// Byte count chosen by the `X-Reset-After-Bytes` request header, if any
+pub fn reset_after_from_headers(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(RESET_AFTER_BYTES_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Response body that fails after exactly `limit` bytes, which makes the server abort
+/// the connection mid-body. The size hint of the full body is kept, so the client
+/// expects more than it gets.
+pub struct ResetAfter {
+    inner: ProxyBody,
+    remaining: u64,
+    flushed: bool,
+}
+
+impl ResetAfter {
+    pub fn new(inner: ProxyBody, limit: u64) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+            flushed: false,
+        }
+    }
+}
+
+impl Body for ResetAfter {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+
+        if this.remaining == 0 {
+            // yield once, so the server writes out what was sent before the connection drops
+            if !this.flushed {
+                this.flushed = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            return Poll::Ready(Some(Err("connection reset by fault injection".into())));
+        }
+
+        let frame = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            other => return Poll::Ready(other),
+        };
+
+        match frame.into_data() {
+            Ok(mut data) => {
+                if data.len() as u64 > this.remaining {
+                    data.truncate(this.remaining as usize);
+                }
+                this.remaining -= data.len() as u64;
+                Poll::Ready(Some(Ok(Frame::data(data))))
+            }
+            Err(frame) => Poll::Ready(Some(Ok(frame))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining > 0 && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
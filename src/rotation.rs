@@ -0,0 +1,110 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// When a log file is rotated, and how many rotated files are kept
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub interval: Option<Duration>,
+    pub keep: usize,
+}
+
+impl Rotation {
+    pub fn new(max_size: Option<u64>, interval_secs: Option<u64>, keep: usize) -> Self {
+        Self {
+            max_size,
+            interval: interval_secs.map(Duration::from_secs),
+            keep,
+        }
+    }
+}
+
+/// Append-only log file that is rotated by size or age.
+///
+/// On rotation `log` becomes `log.1`, `log.1` becomes `log.2` and so on, and files beyond
+/// `keep` are deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    opened_at: Instant,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            file,
+            size,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        let too_large = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + incoming as u64 > max_size);
+        let too_old = self
+            .rotation
+            .interval
+            .is_some_and(|interval| self.opened_at.elapsed() >= interval);
+
+        too_large || too_old
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.rotation.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated_path(self.rotation.keep));
+            for index in (1..self.rotation.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened_at = Instant::now();
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
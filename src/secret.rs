@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+/// A secret flag value, kept out of debug output and configuration dumps.
+#[derive(Clone, Deserialize)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[REDACTED]")
+    }
+}
+
+/// Parse a secret flag, resolving `${env:NAME}` and `${file:PATH}` references
+pub fn parse_secret(value: &str) -> Result<Secret, String> {
+    interpolate(value).map(Secret)
+}
+
+/// Replace `${env:NAME}` with the environment variable and `${file:PATH}` with the file's
+/// contents, without the trailing newline
+pub fn interpolate(value: &str) -> Result<String, String> {
+    let resolved = resolve_references(value.as_bytes(), &[Reference::Env, Reference::File], true)?;
+    String::from_utf8(resolved).map_err(|error| format!("resolved value is not UTF-8: {error}"))
+}
+
+/// Kind of `${KIND:...}` reference
+#[derive(Clone, Copy, PartialEq)]
+pub enum Reference {
+    /// `${env:NAME}`, an environment variable
+    Env,
+    /// `${file:PATH}`, a file's contents without the trailing newline
+    File,
+}
+
+impl Reference {
+    fn kind(self) -> &'static str {
+        match self {
+            Reference::Env => "env",
+            Reference::File => "file",
+        }
+    }
+
+    fn usage(self) -> &'static str {
+        match self {
+            Reference::Env => "${env:NAME}",
+            Reference::File => "${file:PATH}",
+        }
+    }
+
+    fn resolve(self, name: &str) -> Result<String, String> {
+        match self {
+            Reference::Env => {
+                std::env::var(name).map_err(|error| format!("environment variable {name}: {error}"))
+            }
+            Reference::File => std::fs::read_to_string(name)
+                .map(|contents| contents.trim_end_matches(['\r', '\n']).to_owned())
+                .map_err(|error| format!("secret file {name}: {error}")),
+        }
+    }
+}
+
+/// Replace the references of the given kinds in `text`. With `strict`, any other `${` is an
+/// error; otherwise it is kept as-is, as it may belong to the text's own format.
+pub fn resolve_references(
+    text: &[u8],
+    references: &[Reference],
+    strict: bool,
+) -> Result<Vec<u8>, String> {
+    let mut resolved = Vec::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.windows(2).position(|window| window == b"${") {
+        resolved.extend_from_slice(&rest[..start]);
+
+        let reference = &rest[start + 2..];
+        let end = reference.iter().position(|byte| *byte == b'}');
+        let known = references.iter().copied().find(|known| {
+            let prefix = known.kind().len();
+            reference.starts_with(known.kind().as_bytes()) && reference.get(prefix) == Some(&b':')
+        });
+
+        let Some(known) = known else {
+            if strict {
+                let text = String::from_utf8_lossy(text);
+                return Err(match end {
+                    None => format!("unterminated reference in {text:?}"),
+                    Some(end) => format!(
+                        "unknown reference ${{{}}}, expected {}",
+                        String::from_utf8_lossy(&reference[..end]),
+                        references
+                            .iter()
+                            .map(|known| known.usage())
+                            .collect::<Vec<_>>()
+                            .join(" or ")
+                    ),
+                });
+            }
+            resolved.extend_from_slice(b"${");
+            rest = reference;
+            continue;
+        };
+
+        let Some(end) = end else {
+            return Err(format!("unterminated ${{{}:...}} reference", known.kind()));
+        };
+        let name = std::str::from_utf8(&reference[known.kind().len() + 1..end])
+            .map_err(|_| format!("name of a ${{{}:...}} reference is not UTF-8", known.kind()))?;
+        resolved.extend_from_slice(known.resolve(name)?.as_bytes());
+
+        rest = &reference[end + 1..];
+    }
+
+    resolved.extend_from_slice(rest);
+    Ok(resolved)
+}
@@ -5,6 +5,7 @@ pub enum ShutdownType {
     Graceful,
 }
 
+/// Wait for an exit signal, and return once the process may exit.
 pub async fn handle_shutdown(file_descriptors: FileDescriptors) {
     use tokio::signal::unix;
 
@@ -28,9 +29,7 @@ pub async fn handle_shutdown(file_descriptors: FileDescriptors) {
     };
 
     match shutdown_type {
-        ShutdownType::Immediate => {
-            std::process::exit(0);
-        }
+        ShutdownType::Immediate => {}
         ShutdownType::Graceful => {
             log::info!("Graceful shutdown started");
             std::thread::sleep(std::time::Duration::from_secs(5));
@@ -45,7 +44,6 @@ pub async fn handle_shutdown(file_descriptors: FileDescriptors) {
             }
 
             log::info!("Graceful shutdown completed");
-            std::process::exit(0);
         }
     }
 }
@@ -0,0 +1,205 @@
+use hyper::header::HeaderValue;
+use hyper::HeaderMap;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::clock;
+
+const GITHUB_SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+const STRIPE_SIGNATURE_HEADER: &str = "Stripe-Signature";
+
+/// Maximum age of a Stripe-style signature timestamp
+const STRIPE_TOLERANCE_SECS: u64 = 300;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignatureStyle {
+    /// `X-Hub-Signature-256: sha256=<hex hmac(body)>`
+    #[default]
+    Github,
+    /// `Stripe-Signature: t=<timestamp>,v1=<hex hmac("<timestamp>.<body>")>`
+    Stripe,
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Malformed,
+    Expired,
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "signature header is missing"),
+            SignatureError::Malformed => write!(f, "signature header is malformed"),
+            SignatureError::Expired => write!(f, "signature timestamp is too old"),
+            SignatureError::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+/// HMAC-SHA256 webhook signatures, in the style of GitHub or Stripe.
+pub struct WebhookSignature {
+    style: SignatureStyle,
+    key: hmac::Key,
+}
+
+impl WebhookSignature {
+    pub fn new(style: SignatureStyle, secret: &str) -> Self {
+        Self {
+            style,
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        }
+    }
+
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), SignatureError> {
+        match self.style {
+            SignatureStyle::Github => {
+                let signature = headers
+                    .get(GITHUB_SIGNATURE_HEADER)
+                    .ok_or(SignatureError::Missing)?
+                    .to_str()
+                    .map_err(|_| SignatureError::Malformed)?;
+
+                let signature = signature
+                    .strip_prefix("sha256=")
+                    .and_then(|signature| hex::decode(signature).ok())
+                    .ok_or(SignatureError::Malformed)?;
+
+                hmac::verify(&self.key, body, &signature).map_err(|_| SignatureError::Mismatch)
+            }
+            SignatureStyle::Stripe => {
+                let header = headers
+                    .get(STRIPE_SIGNATURE_HEADER)
+                    .ok_or(SignatureError::Missing)?
+                    .to_str()
+                    .map_err(|_| SignatureError::Malformed)?;
+
+                let mut timestamp = None;
+                let mut signatures = Vec::new();
+                for item in header.split(',') {
+                    match item.trim().split_once('=') {
+                        Some(("t", value)) => timestamp = value.parse::<u64>().ok(),
+                        Some(("v1", value)) => signatures.extend(hex::decode(value).ok()),
+                        _ => {}
+                    }
+                }
+
+                let timestamp = timestamp.ok_or(SignatureError::Malformed)?;
+                if signatures.is_empty() {
+                    return Err(SignatureError::Malformed);
+                }
+
+                if clock::unix_now().saturating_sub(timestamp) > STRIPE_TOLERANCE_SECS {
+                    return Err(SignatureError::Expired);
+                }
+
+                let payload = stripe_payload(timestamp, body);
+                signatures
+                    .iter()
+                    .find(|signature| hmac::verify(&self.key, &payload, signature).is_ok())
+                    .map(|_| ())
+                    .ok_or(SignatureError::Mismatch)
+            }
+        }
+    }
+
+    /// Add the signature header for `body`, replacing any existing one
+    pub fn sign(&self, headers: &mut HeaderMap, body: &[u8]) {
+        let (header, value) = match self.style {
+            SignatureStyle::Github => {
+                let signature = hmac::sign(&self.key, body);
+                (
+                    GITHUB_SIGNATURE_HEADER,
+                    format!("sha256={}", hex::encode(signature.as_ref())),
+                )
+            }
+            SignatureStyle::Stripe => {
+                let timestamp = clock::unix_now();
+                let signature = hmac::sign(&self.key, &stripe_payload(timestamp, body));
+                (
+                    STRIPE_SIGNATURE_HEADER,
+                    format!("t={timestamp},v1={}", hex::encode(signature.as_ref())),
+                )
+            }
+        };
+
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(header, value);
+        }
+    }
+}
+
+fn stripe_payload(timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{timestamp}.").into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+
+    #[test]
+    fn verifies_the_github_example() {
+        // from GitHub's guide to validating webhook deliveries
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            GITHUB_SIGNATURE_HEADER,
+            HeaderValue::from_static(
+                "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+            ),
+        );
+
+        let github = WebhookSignature::new(SignatureStyle::Github, SECRET);
+        assert!(github.verify(&headers, b"Hello, World!").is_ok());
+        assert!(matches!(
+            github.verify(&headers, b"Hello, World?"),
+            Err(SignatureError::Mismatch)
+        ));
+        assert!(matches!(
+            github.verify(&HeaderMap::new(), b"Hello, World!"),
+            Err(SignatureError::Missing)
+        ));
+    }
+
+    #[test]
+    fn verifies_what_it_signs() {
+        for style in [SignatureStyle::Github, SignatureStyle::Stripe] {
+            let signature = WebhookSignature::new(style, SECRET);
+            let mut headers = HeaderMap::new();
+            signature.sign(&mut headers, b"{\"id\":1}");
+
+            assert!(signature.verify(&headers, b"{\"id\":1}").is_ok());
+            assert!(WebhookSignature::new(style, "other")
+                .verify(&headers, b"{\"id\":1}")
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_old_and_malformed_stripe_signatures() {
+        let stripe = WebhookSignature::new(SignatureStyle::Stripe, SECRET);
+        let timestamp = clock::unix_now() - STRIPE_TOLERANCE_SECS - 10;
+        let signature = hmac::sign(&stripe.key, &stripe_payload(timestamp, b"{}"));
+
+        let mut headers = HeaderMap::new();
+        let value = format!("t={timestamp},v1={}", hex::encode(signature.as_ref()));
+        headers.insert(STRIPE_SIGNATURE_HEADER, value.parse().unwrap());
+        assert!(matches!(
+            stripe.verify(&headers, b"{}"),
+            Err(SignatureError::Expired)
+        ));
+
+        headers.insert(STRIPE_SIGNATURE_HEADER, HeaderValue::from_static("v1=abcd"));
+        assert!(matches!(
+            stripe.verify(&headers, b"{}"),
+            Err(SignatureError::Malformed)
+        ));
+    }
+}
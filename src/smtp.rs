@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::clock;
+
+/// Largest message accepted
+const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Longest command line, with its CRLF (RFC 5321 4.5.3.1.4)
+const MAX_COMMAND_LINE: usize = 512;
+
+/// Minimal SMTP listener that accepts every message and stores it as an `.eml` file,
+/// so email-sending flows can be verified without a real mail server.
+///
+/// The envelope sender and recipients are recorded as `X-Envelope-From` and
+/// `X-Envelope-To` headers on top of the message.
+pub struct SmtpCapture {
+    dir: PathBuf,
+    received: AtomicU64,
+}
+
+#[derive(Default)]
+struct Envelope {
+    from: Option<String>,
+    to: Vec<String>,
+}
+
+impl SmtpCapture {
+    pub fn new(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            received: AtomicU64::new(0),
+        })
+    }
+
+    /// Accept connections on the listener until the process exits
+    pub fn serve(self: Arc<Self>, listener: TcpListener) {
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let capture = self.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = capture.session(stream).await {
+                        log::debug!("SMTP session ended: {error}");
+                    }
+                });
+            }
+        });
+    }
+
+    async fn session(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut envelope = Envelope::default();
+        let mut line = Vec::new();
+
+        writer.write_all(b"220 affogato SMTP capture\r\n").await?;
+
+        loop {
+            match read_line(&mut reader, &mut line, MAX_COMMAND_LINE).await? {
+                Line::Eof => return Ok(()),
+                Line::TooLong => {
+                    writer.write_all(b"500 Line too long\r\n").await?;
+                    continue;
+                }
+                Line::Complete => {}
+            }
+
+            let command = String::from_utf8_lossy(&line);
+            let command = command.trim_end();
+            let verb = command
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_ascii_uppercase();
+            let argument = command.get(verb.len()..).unwrap_or("").trim();
+
+            let reply: &[u8] = match verb.as_str() {
+                "HELO" => b"250 affogato\r\n",
+                "EHLO" => b"250-affogato\r\n250-8BITMIME\r\n250 SIZE 10485760\r\n",
+                "MAIL" => {
+                    envelope = Envelope {
+                        from: Some(address(argument)),
+                        to: Vec::new(),
+                    };
+                    b"250 OK\r\n"
+                }
+                "RCPT" if envelope.from.is_some() => {
+                    envelope.to.push(address(argument));
+                    b"250 OK\r\n"
+                }
+                "DATA" if !envelope.to.is_empty() => {
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .await?;
+                    match read_data(&mut reader).await? {
+                        Some(data) => {
+                            self.store(&std::mem::take(&mut envelope), &data).await;
+                            b"250 OK: message captured\r\n"
+                        }
+                        None => b"552 Message size exceeds the limit\r\n",
+                    }
+                }
+                "RCPT" | "DATA" => b"503 Bad sequence of commands\r\n",
+                "RSET" => {
+                    envelope = Envelope::default();
+                    b"250 OK\r\n"
+                }
+                "NOOP" => b"250 OK\r\n",
+                "QUIT" => {
+                    writer.write_all(b"221 Bye\r\n").await?;
+                    return Ok(());
+                }
+                _ => b"502 Command not implemented\r\n",
+            };
+
+            writer.write_all(reply).await?;
+        }
+    }
+
+    async fn store(&self, envelope: &Envelope, data: &[u8]) {
+        let index = self.received.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}-{index}.eml", clock::unix_now()));
+
+        let mut message = format!(
+            "X-Envelope-From: {}\r\nX-Envelope-To: {}\r\n",
+            envelope.from.as_deref().unwrap_or(""),
+            envelope.to.join(", ")
+        )
+        .into_bytes();
+        message.extend_from_slice(data);
+
+        match tokio::fs::write(&path, message).await {
+            Ok(()) => log::info!(
+                "Captured email from {} to {} as {}",
+                envelope.from.as_deref().unwrap_or("<>"),
+                envelope.to.join(", "),
+                path.display()
+            ),
+            Err(error) => log::error!("Failed to store email {}: {error}", path.display()),
+        }
+    }
+}
+
+/// The address in `FROM:<address>` or `TO:<address>`
+fn address(argument: &str) -> String {
+    let argument = argument
+        .split_once(':')
+        .map_or(argument, |(_, address)| address);
+
+    argument
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_owned()
+}
+
+/// How reading a line ended
+enum Line {
+    Eof,
+    Complete,
+    /// The line was longer than the limit, and was consumed without being kept
+    TooLong,
+}
+
+/// Read a line of at most `limit` bytes into `line`, so a client cannot grow the buffer
+/// by never sending a newline
+async fn read_line<R>(reader: &mut R, line: &mut Vec<u8>, limit: usize) -> std::io::Result<Line>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    line.clear();
+    let read = (&mut *reader)
+        .take(limit as u64)
+        .read_until(b'\n', line)
+        .await?;
+    if read == 0 {
+        return Ok(Line::Eof);
+    }
+    // a line cut short by the end of the stream is still a line
+    if line.ends_with(b"\n") || read < limit {
+        return Ok(Line::Complete);
+    }
+
+    line.clear();
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(Line::TooLong);
+        }
+        match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(Line::TooLong);
+            }
+            None => {
+                let skipped = available.len();
+                reader.consume(skipped);
+            }
+        }
+    }
+}
+
+/// Read the message up to the terminating dot, undoing dot-stuffing.
+/// None if it grows beyond the size limit; the rest of it is still consumed.
+async fn read_data<R>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut data = Vec::new();
+    let mut too_large = false;
+    let mut line = Vec::new();
+
+    loop {
+        match read_line(reader, &mut line, MAX_MESSAGE_SIZE).await? {
+            Line::Eof => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Line::TooLong => {
+                too_large = true;
+                data.clear();
+                continue;
+            }
+            Line::Complete => {}
+        }
+
+        if line == b".\r\n" || line == b".\n" {
+            return Ok((!too_large).then_some(data));
+        }
+
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+        if data.len() + line.len() > MAX_MESSAGE_SIZE {
+            too_large = true;
+            data.clear();
+        }
+        if !too_large {
+            data.extend_from_slice(line);
+        }
+    }
+}
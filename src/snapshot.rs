@@ -0,0 +1,152 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::{Method, StatusCode};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of characters of the body excerpt shown when a snapshot does not match
+const DIFF_EXCERPT_LEN: usize = 120;
+
+/// Records proxied responses as snapshot files, and compares later responses against them.
+///
+/// Snapshots are keyed by a hash of the request method, URI and body. A missing snapshot is
+/// recorded; an existing one is compared by status and body, and mismatches are counted
+/// as regressions.
+pub struct SnapshotStore {
+    dir: PathBuf,
+    update: bool,
+    regressions: AtomicUsize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    method: String,
+    uri: String,
+    status: u16,
+    body: String,
+    #[serde(default)]
+    body_base64: bool,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: PathBuf, update: bool) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            update,
+            regressions: AtomicUsize::new(0),
+        })
+    }
+
+    /// Key identifying the snapshot of a request
+    pub fn key(&self, method: &Method, uri: &str, body: &[u8]) -> String {
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(method.as_str().as_bytes());
+        context.update(b" ");
+        context.update(uri.as_bytes());
+        context.update(b"\n");
+        context.update(body);
+
+        hex::encode(&context.finish().as_ref()[..16])
+    }
+
+    pub async fn check(
+        &self,
+        key: &str,
+        method: &Method,
+        uri: &str,
+        status: StatusCode,
+        body: &[u8],
+    ) {
+        let path = self.dir.join(format!("{key}.json"));
+
+        let live = match std::str::from_utf8(body) {
+            Ok(text) => Snapshot {
+                method: method.to_string(),
+                uri: uri.to_owned(),
+                status: status.as_u16(),
+                body: text.to_owned(),
+                body_base64: false,
+            },
+            Err(_) => Snapshot {
+                method: method.to_string(),
+                uri: uri.to_owned(),
+                status: status.as_u16(),
+                body: STANDARD.encode(body),
+                body_base64: true,
+            },
+        };
+
+        let recorded = match tokio::fs::read(&path).await {
+            Ok(recorded) if !self.update => recorded,
+            _ => {
+                let Ok(serialized) = serde_json::to_vec_pretty(&live) else {
+                    return;
+                };
+                match tokio::fs::write(&path, serialized).await {
+                    Ok(()) => {
+                        log::debug!("Recorded snapshot {} for {method} {uri}", path.display())
+                    }
+                    Err(error) => {
+                        log::error!("Failed to write snapshot {}: {error}", path.display())
+                    }
+                }
+                return;
+            }
+        };
+
+        let recorded: Snapshot = match serde_json::from_slice(&recorded) {
+            Ok(recorded) => recorded,
+            Err(error) => {
+                log::error!("Failed to read snapshot {}: {error}", path.display());
+                return;
+            }
+        };
+
+        if recorded.status != live.status {
+            self.regressions.fetch_add(1, Ordering::SeqCst);
+            log::warn!(
+                "Snapshot regression for {method} {uri}: status {} (expected {}), snapshot {}",
+                live.status,
+                recorded.status,
+                path.display()
+            );
+        } else if recorded.body != live.body || recorded.body_base64 != live.body_base64 {
+            self.regressions.fetch_add(1, Ordering::SeqCst);
+            log::warn!(
+                "Snapshot regression for {method} {uri}: {}, snapshot {}",
+                describe_difference(&recorded.body, &live.body),
+                path.display()
+            );
+        }
+    }
+
+    pub fn regressions(&self) -> usize {
+        self.regressions.load(Ordering::SeqCst)
+    }
+}
+
+/// Point out where two bodies start to differ
+fn describe_difference(expected: &str, actual: &str) -> String {
+    let offset = expected
+        .char_indices()
+        .zip(actual.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((index, _), _)| index)
+        .unwrap_or(expected.len().min(actual.len()));
+
+    // the bodies are equal up to `offset`, so it is a char boundary in both
+    let excerpt = |body: &str| {
+        let excerpt: String = body[offset..].chars().take(DIFF_EXCERPT_LEN).collect();
+        format!("{excerpt:?}")
+    };
+
+    format!(
+        "body differs at byte {offset}: expected {}, got {}",
+        excerpt(expected),
+        excerpt(actual)
+    )
+}
@@ -0,0 +1,218 @@
+use hyper::{Method, Request, Response};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::path::{Path, PathBuf};
+
+use crate::body::{full, ProxyBody};
+
+const INDEX_FILE: &str = "index.html";
+
+/// Characters escaped in listing links
+const HREF_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Serves local directories under URL path prefixes, for requests without `Proxy-Host`.
+pub struct StaticFiles {
+    routes: Vec<(String, PathBuf)>,
+    listing: bool,
+}
+
+impl StaticFiles {
+    pub fn new(mut routes: Vec<(String, PathBuf)>, listing: bool) -> Self {
+        // the longest prefix wins
+        routes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+
+        Self { routes, listing }
+    }
+
+    /// Fail if a route's directory is missing. Returns the number of routes.
+    pub async fn load(&self) -> Result<usize, String> {
+        for (prefix, dir) in &self.routes {
+            if !tokio::fs::metadata(dir)
+                .await
+                .is_ok_and(|metadata| metadata.is_dir())
+            {
+                return Err(format!("{} for {prefix} is not a directory", dir.display()));
+            }
+        }
+
+        Ok(self.routes.len())
+    }
+
+    /// The directory serving the path, and the path relative to the route prefix
+    fn route<'a>(&self, path: &'a str) -> Option<(&Path, &'a str)> {
+        self.routes.iter().find_map(|(prefix, dir)| {
+            let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+            (rest.is_empty() || rest.starts_with('/')).then_some((dir.as_path(), rest))
+        })
+    }
+
+    /// None if no route serves the path
+    pub async fn handle_request<B>(&self, request: &Request<B>) -> Option<Response<ProxyBody>> {
+        let path = request.uri().path();
+        let (dir, rest) = self.route(path)?;
+
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            return Some(
+                Response::builder()
+                    .status(405)
+                    .header(hyper::header::ALLOW, "GET, HEAD")
+                    .body(full("Method not allowed"))
+                    .unwrap(),
+            );
+        }
+
+        let Some(file_path) = resolve(dir, rest) else {
+            return Some(not_found());
+        };
+
+        let Ok(metadata) = tokio::fs::metadata(&file_path).await else {
+            return Some(not_found());
+        };
+
+        if metadata.is_dir() {
+            // relative links in index files and listings need the trailing slash
+            if !path.ends_with('/') {
+                let mut location = format!("{path}/");
+                if let Some(query) = request.uri().query() {
+                    location.push('?');
+                    location.push_str(query);
+                }
+                return Some(
+                    Response::builder()
+                        .status(301)
+                        .header(hyper::header::LOCATION, location)
+                        .body(full(""))
+                        .unwrap(),
+                );
+            }
+
+            let index = file_path.join(INDEX_FILE);
+            if tokio::fs::metadata(&index)
+                .await
+                .is_ok_and(|index| index.is_file())
+            {
+                return Some(serve_file(&index).await);
+            }
+
+            if self.listing {
+                return Some(list_directory(&file_path, path).await);
+            }
+
+            return Some(not_found());
+        }
+
+        Some(serve_file(&file_path).await)
+    }
+}
+
+/// Parse a `PREFIX=DIR` route, or a bare `DIR` served at `/`
+pub fn parse_static_route(route: &str) -> Result<(String, PathBuf), String> {
+    let (prefix, dir) = route.split_once('=').unwrap_or(("/", route));
+
+    if !prefix.starts_with('/') {
+        return Err(format!("route prefix must start with '/': {prefix}"));
+    }
+
+    Ok((prefix.to_owned(), PathBuf::from(dir)))
+}
+
+/// Map the URL path onto the directory, refusing anything that would leave it
+fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let mut resolved = dir.to_owned();
+
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = percent_encoding::percent_decode_str(segment)
+            .decode_utf8()
+            .ok()?;
+        if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        resolved.push(segment.as_ref());
+    }
+
+    Some(resolved)
+}
+
+async fn serve_file(path: &Path) -> Response<ProxyBody> {
+    let Ok(contents) = tokio::fs::read(path).await else {
+        return not_found();
+    };
+
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+
+    let mut response = Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, content_type.as_ref());
+
+    if let Ok(modified) = tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+    {
+        response = response.header(
+            hyper::header::LAST_MODIFIED,
+            httpdate::fmt_http_date(modified),
+        );
+    }
+
+    response.body(full(contents)).unwrap()
+}
+
+async fn list_directory(dir: &Path, url_path: &str) -> Response<ProxyBody> {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return not_found();
+    };
+
+    let mut names = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry
+            .file_type()
+            .await
+            .is_ok_and(|file_type| file_type.is_dir())
+        {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    let title = escape_html(url_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body><h1>Index of {title}</h1>\n<ul>\n"
+    );
+    if url_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in &names {
+        let href = utf8_percent_encode(name, HREF_ESCAPE);
+        html.push_str(&format!(
+            "<li><a href=\"{href}\">{}</a></li>\n",
+            escape_html(name)
+        ));
+    }
+    html.push_str("</ul></body></html>\n");
+
+    Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(full(html))
+        .unwrap()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn not_found() -> Response<ProxyBody> {
+    Response::builder()
+        .status(404)
+        .body(full("Not found"))
+        .unwrap()
+}
@@ -0,0 +1,180 @@
+use hyper::Response;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::body::{full, ProxyBody};
+use crate::timing::ConnectTiming;
+
+/// Open client connections
+pub static CONNECTIONS: Gauge = Gauge::new();
+
+/// Requests currently waiting on an upstream
+pub static UPSTREAM_IN_FLIGHT: Gauge = Gauge::new();
+
+/// Requests without `Proxy-Host` that nothing answered
+pub static UNMATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// New and reused upstream connections, by upstream
+pub static UPSTREAM_CONNECTIONS: ConnectionReuse = ConnectionReuse::new();
+
+/// Highest number of alive tokio tasks seen so far
+static TASKS_HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+
+/// A current value with its high-water mark
+pub struct Gauge {
+    current: AtomicUsize,
+    high_water: AtomicUsize,
+}
+
+impl Gauge {
+    const fn new() -> Self {
+        Self {
+            current: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count one more, until the returned guard is dropped
+    pub fn track(&'static self) -> GaugeGuard {
+        let current = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water.fetch_max(current, Ordering::Relaxed);
+        sample_tasks();
+
+        GaugeGuard(self)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "current": self.current.load(Ordering::Relaxed),
+            "high_water": self.high_water.load(Ordering::Relaxed),
+        })
+    }
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts responses by whether their upstream connection was new or reused.
+///
+/// Connections are registered when the client opens them, by local address, and the first
+/// response on a registered address is counted as new. A port the OS hands out again is
+/// registered again, so it is not mistaken for a reused connection. Only connections that
+/// never got a response stay registered, at most one per local address.
+pub struct ConnectionReuse {
+    opened: Mutex<BTreeMap<SocketAddr, ConnectTiming>>,
+    upstreams: Mutex<BTreeMap<String, UpstreamConnections>>,
+}
+
+#[derive(Default)]
+struct UpstreamConnections {
+    new: u64,
+    reused: u64,
+}
+
+impl ConnectionReuse {
+    const fn new() -> Self {
+        Self {
+            opened: Mutex::new(BTreeMap::new()),
+            upstreams: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register a connection the client has just opened, with the time it took
+    pub fn opened(&self, local_addr: SocketAddr, setup: ConnectTiming) {
+        self.opened.lock().unwrap().insert(local_addr, setup);
+    }
+
+    /// Count a response from the upstream, returning the setup of its connection when
+    /// it is new
+    pub fn record(&self, upstream: &str, local_addr: Option<SocketAddr>) -> Option<ConnectTiming> {
+        let local_addr = local_addr?;
+
+        let setup = self.opened.lock().unwrap().remove(&local_addr);
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let connections = upstreams.entry(upstream.to_owned()).or_default();
+        if setup.is_some() {
+            connections.new += 1;
+        } else {
+            connections.reused += 1;
+        }
+        setup
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let upstreams = self.upstreams.lock().unwrap();
+        upstreams
+            .iter()
+            .map(|(upstream, connections)| {
+                (
+                    upstream.clone(),
+                    json!({ "new": connections.new, "reused": connections.reused }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+fn alive_tasks() -> usize {
+    tokio::runtime::Handle::current()
+        .metrics()
+        .num_alive_tasks()
+}
+
+fn sample_tasks() {
+    TASKS_HIGH_WATER.fetch_max(alive_tasks(), Ordering::Relaxed);
+}
+
+/// Current gauges, and the filesystem mock stats if enabled, as a JSON response
+pub fn response(mock_stats: Option<serde_json::Value>) -> Response<ProxyBody> {
+    sample_tasks();
+
+    let mut stats = json!({
+        "unmatched_requests": UNMATCHED.load(Ordering::Relaxed),
+        "connections": CONNECTIONS.to_json(),
+        "upstream_in_flight": UPSTREAM_IN_FLIGHT.to_json(),
+        "upstream_connections": UPSTREAM_CONNECTIONS.to_json(),
+        "tasks": {
+            "current": alive_tasks(),
+            "high_water": TASKS_HIGH_WATER.load(Ordering::Relaxed),
+        },
+    });
+    if let Some(mock_stats) = mock_stats {
+        stats["mocks"] = mock_stats;
+    }
+
+    Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(full(stats.to_string()))
+        .unwrap()
+}
+
+/// 200 when no request went unmatched so far and every mock expectation is met, 417 with
+/// the recent near misses and the violations otherwise
+pub fn verify_response(
+    near_misses: Option<serde_json::Value>,
+    violations: Vec<serde_json::Value>,
+) -> Response<ProxyBody> {
+    let unmatched = UNMATCHED.load(Ordering::Relaxed);
+    let passed = unmatched == 0 && violations.is_empty();
+
+    let mut report = json!({ "unmatched_requests": unmatched, "violations": violations });
+    if let Some(near_misses) = near_misses {
+        report["recent"] = near_misses;
+    }
+
+    Response::builder()
+        .status(if passed { 200 } else { 417 })
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(full(report.to_string()))
+        .unwrap()
+}
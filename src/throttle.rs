@@ -0,0 +1,174 @@
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::Response;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+
+use crate::body::{BoxError, ProxyBody};
+
+/// Bandwidth caps for response bodies, global or by URL path prefix.
+pub struct BandwidthLimits {
+    global: Option<u64>,
+    routes: Vec<(String, u64)>,
+    burst: Option<u64>,
+}
+
+impl BandwidthLimits {
+    pub fn new(global: Option<u64>, mut routes: Vec<(String, u64)>, burst: Option<u64>) -> Self {
+        // the longest prefix wins
+        routes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+
+        Self {
+            global,
+            routes,
+            burst,
+        }
+    }
+
+    /// Bytes per second allowed for responses to the path
+    pub fn limit(&self, path: &str) -> Option<u64> {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| {
+                path.strip_prefix(prefix.trim_end_matches('/'))
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map(|(_, limit)| *limit)
+            .or(self.global)
+    }
+
+    /// Throttle the response body to the limit of the path
+    pub fn apply(&self, response: Response<ProxyBody>, path: &str) -> Response<ProxyBody> {
+        let Some(rate) = self.limit(path) else {
+            return response;
+        };
+        let burst = self.burst.unwrap_or(default_burst(rate));
+
+        response.map(|body| Throttled::new(body, rate, burst).boxed())
+    }
+}
+
+/// About 10ms worth of data, and at least 1KiB
+pub fn default_burst(rate: u64) -> u64 {
+    (rate / 100).max(1024)
+}
+
+/// Parse a `PREFIX=BYTES_PER_SEC` bandwidth route
+pub fn parse_bandwidth_route(route: &str) -> Result<(String, u64), String> {
+    let Some((prefix, rate)) = route.split_once('=') else {
+        return Err(format!(
+            "bandwidth route must be PREFIX=BYTES_PER_SEC: {route}"
+        ));
+    };
+
+    if !prefix.starts_with('/') {
+        return Err(format!("route prefix must start with '/': {prefix}"));
+    }
+
+    let rate = rate
+        .parse::<u64>()
+        .map_err(|error| format!("invalid bandwidth {rate}: {error}"))?;
+    if rate == 0 {
+        return Err("bandwidth must be at least 1 byte per second".to_owned());
+    }
+
+    Ok((prefix.to_owned(), rate))
+}
+
+/// Response body released at `rate` bytes per second, with bursts of up to `burst` bytes
+/// (a token bucket that starts full).
+pub struct Throttled {
+    inner: ProxyBody,
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    pending: Bytes,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Throttled {
+    pub fn new(inner: ProxyBody, rate: u64, burst: u64) -> Self {
+        let burst = burst.max(1) as f64;
+
+        Self {
+            inner,
+            rate: rate.max(1) as f64,
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+            pending: Bytes::new(),
+            sleep: None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+    }
+}
+
+impl Body for Throttled {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(sleep) = &mut this.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+
+            if this.pending.is_empty() {
+                let frame = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                    Some(Ok(frame)) => frame,
+                    other => return Poll::Ready(other),
+                };
+                match frame.into_data() {
+                    Ok(data) => this.pending = data,
+                    // trailers are not throttled
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                }
+                continue;
+            }
+
+            this.refill();
+
+            // wait for a full burst, or what is left of the chunk
+            let wanted = (this.pending.len() as f64).min(this.burst);
+            if this.tokens < wanted {
+                let wait = Duration::from_secs_f64((wanted - this.tokens) / this.rate);
+                this.sleep = Some(Box::pin(tokio::time::sleep(wait)));
+                continue;
+            }
+
+            this.tokens -= wanted;
+            let chunk = this.pending.split_to(wanted as usize);
+            return Poll::Ready(Some(Ok(Frame::data(chunk))));
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut size_hint = self.inner.size_hint();
+        let pending = self.pending.len() as u64;
+        size_hint.set_lower(size_hint.lower() + pending);
+        if let Some(upper) = size_hint.upper() {
+            size_hint.set_upper(upper + pending);
+        }
+        size_hint
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Where the time of a proxied request went, carried to the outer handler as a response extension.
+///
+/// `dns` and `connect` are only spent by requests that waited for a new connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpstreamTiming {
+    /// waiting for a free slot on the upstream
+    pub queue: Duration,
+    /// resolving the upstream host name
+    pub dns: Duration,
+    /// connecting to the upstream, including the TLS handshake
+    pub connect: Duration,
+    /// from sending the request until the upstream response head arrived, without
+    /// `dns` and `connect`
+    pub ttfb: Duration,
+    /// reading the upstream response body
+    pub body: Duration,
+}
+
+impl fmt::Display for UpstreamTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queue {:.3}ms, dns {:.3}ms, connect {:.3}ms, ttfb {:.3}ms, body {:.3}ms",
+            millis(self.queue),
+            millis(self.dns),
+            millis(self.connect),
+            millis(self.ttfb),
+            millis(self.body),
+        )
+    }
+}
+
+/// Setup of a new upstream connection, measured by the client's connector
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectTiming {
+    pub dns: Duration,
+    pub connect: Duration,
+    /// when the connection was ready for requests
+    pub ready_at: Instant,
+}
+
+impl UpstreamTiming {
+    /// Move the setup of the connection the request waited for out of `ttfb`
+    pub fn add_connection_setup(&mut self, setup: ConnectTiming) {
+        self.dns = setup.dns;
+        self.connect = setup.connect;
+        self.ttfb = self.ttfb.saturating_sub(setup.dns + setup.connect);
+    }
+
+    /// `Server-Timing` header value, with the total time spent in the proxy
+    pub fn server_timing(timing: Option<&Self>, total: Duration) -> String {
+        let mut value = String::new();
+        if let Some(timing) = timing {
+            value.push_str(&format!(
+                "queue;dur={:.3}, dns;dur={:.3}, connect;dur={:.3}, ttfb;dur={:.3}, body;dur={:.3}, ",
+                millis(timing.queue),
+                millis(timing.dns),
+                millis(timing.connect),
+                millis(timing.ttfb),
+                millis(timing.body),
+            ));
+        }
+        value.push_str(&format!("total;dur={:.3}", millis(total)));
+        value
+    }
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
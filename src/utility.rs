@@ -0,0 +1,150 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http_body_util::BodyExt;
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Map, Value};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::body::{full, ProxyBody};
+
+/// Longest delay served by /delay
+const MAX_DELAY_SECS: f64 = 60.0;
+
+/// Largest body served by /bytes
+const MAX_BYTES: usize = 10 * 1024 * 1024;
+
+/// httpbin-style endpoints for testing HTTP clients, served under a path prefix:
+/// `/echo`, `/status/{code}`, `/delay/{secs}`, `/headers`, `/ip` and `/bytes/{n}`.
+pub struct UtilityRoutes {
+    prefix: String,
+}
+
+impl UtilityRoutes {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    fn route<'a>(&self, path: &'a str) -> Option<&'a str> {
+        path.strip_prefix(&self.prefix)
+            .filter(|route| route.starts_with('/'))
+    }
+
+    /// Whether the path belongs to the utility endpoints
+    pub fn handles(&self, path: &str) -> bool {
+        let Some(route) = self.route(path) else {
+            return false;
+        };
+
+        matches!(route, "/echo" | "/headers" | "/ip")
+            || ["/status/", "/delay/", "/bytes/"]
+                .iter()
+                .any(|prefix| route.starts_with(prefix))
+    }
+
+    pub async fn handle_request(
+        &self,
+        request: Request<hyper::body::Incoming>,
+        client_addr: SocketAddr,
+    ) -> Response<ProxyBody> {
+        let path = request.uri().path().to_owned();
+        let route = self.route(&path).unwrap_or("");
+
+        if let Some(code) = route.strip_prefix("/status/") {
+            return match code
+                .parse::<u16>()
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+            {
+                Some(status) => Response::builder().status(status).body(full("")).unwrap(),
+                None => text_response(400, format!("Invalid status code: {code}")),
+            };
+        }
+
+        if let Some(secs) = route.strip_prefix("/delay/") {
+            let Some(secs) = secs.parse::<f64>().ok().filter(|secs| *secs >= 0.0) else {
+                return text_response(400, format!("Invalid delay: {secs}"));
+            };
+            let delay = Duration::from_secs_f64(secs.min(MAX_DELAY_SECS));
+            tokio::time::sleep(delay).await;
+            return json_response(&json!({ "delay": delay.as_secs_f64() }));
+        }
+
+        if let Some(length) = route.strip_prefix("/bytes/") {
+            let Some(length) = length.parse::<usize>().ok() else {
+                return text_response(400, format!("Invalid length: {length}"));
+            };
+            let mut bytes = vec![0u8; length.min(MAX_BYTES)];
+            if SystemRandom::new().fill(&mut bytes).is_err() {
+                return text_response(500, "Failed to generate random bytes");
+            }
+            return Response::builder()
+                .status(200)
+                .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+                .body(full(bytes))
+                .unwrap();
+        }
+
+        match route {
+            "/headers" => json_response(&json!({ "headers": headers_json(request.headers()) })),
+            "/ip" => json_response(&json!({ "origin": client_addr.ip().to_string() })),
+            "/echo" => {
+                let (parts, body) = request.into_parts();
+                let Ok(body) = body.collect().await else {
+                    return text_response(400, "Failed to read request body");
+                };
+                let body = body.to_bytes();
+
+                let (body, encoding) = match std::str::from_utf8(&body) {
+                    Ok(text) => (text.to_owned(), "utf-8"),
+                    Err(_) => (STANDARD.encode(&body), "base64"),
+                };
+
+                json_response(&json!({
+                    "method": parts.method.as_str(),
+                    "path": parts.uri.path(),
+                    "query": parts.uri.query(),
+                    "headers": headers_json(&parts.headers),
+                    "body": body,
+                    "body_encoding": encoding,
+                    "origin": client_addr.ip().to_string(),
+                }))
+            }
+            _ => text_response(404, "Not found"),
+        }
+    }
+}
+
+/// Headers as a JSON object, with repeated headers joined by commas
+fn headers_json(headers: &HeaderMap) -> Value {
+    let mut object = Map::new();
+
+    for name in headers.keys() {
+        let values: Vec<_> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        object.insert(name.to_string(), json!(values.join(", ")));
+    }
+
+    Value::Object(object)
+}
+
+fn json_response(value: &Value) -> Response<ProxyBody> {
+    Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(full(value.to_string()))
+        .unwrap()
+}
+
+fn text_response(status: u16, message: impl Into<String>) -> Response<ProxyBody> {
+    Response::builder()
+        .status(status)
+        .body(full(message.into()))
+        .unwrap()
+}